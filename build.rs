@@ -3,6 +3,11 @@ use std::{env, path::PathBuf, process::Command};
 const INOCHI2D_REPO: &'static str = "https://github.com/Inochi2D/inochi2d.git";
 const INOCHI2D_C_REPO: &'static str = "https://github.com/Inochi2D/inochi2d-c.git";
 
+#[cfg(feature = "runtime")]
+const WITH_RUNTIME: bool = true;
+#[cfg(not(feature = "runtime"))]
+const WITH_RUNTIME: bool = false;
+
 #[cfg(feature = "opengl")]
 const WITH_OPENGL: bool = true;
 #[cfg(not(feature = "opengl"))]
@@ -56,6 +61,11 @@ fn dub_build(dir: &PathBuf, cfg: &'static str) {
 }
 
 fn main() {
+    /* Only the runtime needs the C library, the format support is pure Rust */
+    if !WITH_RUNTIME {
+        return;
+    }
+
     let inochi2d = clone_repo(INOCHI2D_REPO, "inochi2d");
     let inochi2d_c = clone_repo(INOCHI2D_C_REPO, "inochi2d-c");
 
@@ -0,0 +1,43 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+#![no_main]
+
+use inochi2d_rs::format::{self, Container, LoadLimits};
+use inochi2d_rs::texture;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut container = match Container::parse(data) {
+        Ok(container) => container,
+        Err(_) => return,
+    };
+
+    let _ = format::read_meta_from_bytes(data);
+    let _ = format::read_vendor_data_from_bytes(data);
+
+    /* Small limits keep decoding, rendering and migrating fast enough to fuzz */
+    let limits = LoadLimits {
+        max_file_size: 4 * 1024 * 1024,
+        max_texture_dimension: 1024,
+        ..LoadLimits::default()
+    };
+    if format::check_limits(data, &limits).is_err() {
+        return;
+    }
+
+    /* Decode every texture, not only those the thumbnail renderer reaches */
+    for texture in container.textures.iter() {
+        let _ = texture::decode(texture);
+    }
+
+    let _ = container.validate();
+    let _ = format::render_thumbnail(&container, 64);
+
+    if container.migrate().is_ok() {
+        let _ = container.to_bytes();
+    }
+});
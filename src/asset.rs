@@ -0,0 +1,305 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[cfg(feature = "zip-source")]
+use std::io::{Read, Seek};
+#[cfg(feature = "zip-source")]
+use std::sync::Mutex;
+
+#[cfg(feature = "logging")]
+use tracing::debug;
+
+/* Archive headers can claim any size, so don't reserve more than this up front */
+#[cfg(any(feature = "zip-source", feature = "tar-source"))]
+const MAX_PREALLOCATION: u64 = 64 * 1024 * 1024;
+
+/// A source puppet files can be resolved from.
+///
+/// Identifiers are path-like, for archive sources they are the path of the
+/// entry inside of the archive.
+pub trait AssetSource: Send + Sync {
+    /// Read the full contents of the asset `id`.
+    ///
+    /// # Returns
+    ///
+    /// - If the asset exists its contents.
+    /// - Otherwise an `io::Error`, with `io::ErrorKind::NotFound` if the asset does not exist.
+    ///
+    fn load(&self, id: &Path) -> io::Result<Vec<u8>>;
+
+    /// Open the asset `id` for streaming.
+    ///
+    /// The default implementation loads the whole asset up front.
+    ///
+    /// # Returns
+    ///
+    /// - If the asset exists a reader over its contents and its size in bytes, if known.
+    /// - Otherwise an `io::Error`, with `io::ErrorKind::NotFound` if the asset does not exist.
+    ///
+    fn open(&self, id: &Path) -> io::Result<(Box<dyn io::Read + '_>, Option<u64>)> {
+        let data = self.load(id)?;
+        let len = data.len() as u64;
+
+        Ok((Box::new(io::Cursor::new(data)), Some(len)))
+    }
+
+    /// Get when the asset `id` was last modified.
+    ///
+    /// Used to detect changed puppets for hot-reloading, the default implementation
+    /// returns `None` which means the asset is never reloaded.
+    ///
+    fn modified(&self, _id: &Path) -> Option<SystemTime> {
+        None
+    }
+}
+
+fn not_found(id: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("No asset named {}", id.display()),
+    )
+}
+
+/// Loads assets from the filesystem, optionally relative to a root directory.
+#[derive(Debug, Clone, Default)]
+pub struct FileSystemSource {
+    root: PathBuf,
+}
+
+impl FileSystemSource {
+    /// Create a new filesystem source that resolves identifiers relative to `root`.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let source = FileSystemSource::new("./puppets");
+    /// ~~~
+    ///
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FileSystemSource { root: root.into() }
+    }
+}
+
+impl AssetSource for FileSystemSource {
+    fn load(&self, id: &Path) -> io::Result<Vec<u8>> {
+        let path = self.root.join(id);
+        #[cfg(feature = "logging")]
+        debug!("Loading asset {}", path.display());
+        fs::read(path)
+    }
+
+    fn open(&self, id: &Path) -> io::Result<(Box<dyn io::Read + '_>, Option<u64>)> {
+        let file = fs::File::open(self.root.join(id))?;
+        let len = file.metadata().ok().map(|meta| meta.len());
+
+        Ok((Box::new(file), len))
+    }
+
+    fn modified(&self, id: &Path) -> Option<SystemTime> {
+        fs::metadata(self.root.join(id))
+            .and_then(|meta| meta.modified())
+            .ok()
+    }
+}
+
+/// Serves assets out of an in-memory map, useful for tests and generated content.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySource {
+    assets: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl MemorySource {
+    /// Create a new empty in-memory source.
+    pub fn new() -> Self {
+        MemorySource::default()
+    }
+
+    /// Add or replace the asset `id`.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let mut source = MemorySource::new();
+    ///
+    /// source.insert("Ada.inx", include_bytes!("./puppets/Ada.inx").to_vec());
+    /// ~~~
+    ///
+    pub fn insert(&mut self, id: impl Into<PathBuf>, data: Vec<u8>) {
+        self.assets.insert(id.into(), data);
+    }
+
+    /// Remove the asset `id`, returning its contents if it existed.
+    pub fn remove(&mut self, id: &Path) -> Option<Vec<u8>> {
+        self.assets.remove(id)
+    }
+}
+
+impl AssetSource for MemorySource {
+    fn load(&self, id: &Path) -> io::Result<Vec<u8>> {
+        self.assets.get(id).cloned().ok_or_else(|| not_found(id))
+    }
+}
+
+/// Reads assets out of a zip archive.
+#[cfg(feature = "zip-source")]
+pub struct ZipSource<R: Read + Seek> {
+    archive: Mutex<zip::ZipArchive<R>>,
+}
+
+#[cfg(feature = "zip-source")]
+impl<R: Read + Seek> ZipSource<R> {
+    /// Open a zip archive from `reader`.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let source = ZipSource::new(File::open("./content.pak")?)?;
+    /// ~~~
+    ///
+    pub fn new(reader: R) -> io::Result<Self> {
+        Ok(ZipSource {
+            archive: Mutex::new(zip::ZipArchive::new(reader)?),
+        })
+    }
+}
+
+#[cfg(feature = "zip-source")]
+impl<R: Read + Seek + Send> AssetSource for ZipSource<R> {
+    fn load(&self, id: &Path) -> io::Result<Vec<u8>> {
+        let name = id.to_str().ok_or_else(|| not_found(id))?;
+        let mut archive = self.archive.lock().unwrap();
+        let mut file = match archive.by_name(name) {
+            Ok(file) => file,
+            Err(zip::result::ZipError::FileNotFound) => return Err(not_found(id)),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut data = Vec::with_capacity(file.size().min(MAX_PREALLOCATION) as usize);
+        file.read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// Reads assets out of a tar archive.
+///
+/// Tar archives have no index, so all regular files are read into memory when
+/// the source is created.
+#[cfg(feature = "tar-source")]
+#[derive(Debug, Clone)]
+pub struct TarSource {
+    entries: MemorySource,
+}
+
+#[cfg(feature = "tar-source")]
+impl TarSource {
+    /// Read a tar archive from `reader`.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let source = TarSource::new(File::open("./content.tar")?)?;
+    /// ~~~
+    ///
+    pub fn new(reader: impl io::Read) -> io::Result<Self> {
+        let mut entries = MemorySource::new();
+        let mut archive = tar::Archive::new(reader);
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let path = entry.path()?.into_owned();
+            let mut data = Vec::with_capacity(entry.size().min(MAX_PREALLOCATION) as usize);
+            io::Read::read_to_end(&mut entry, &mut data)?;
+            entries.insert(path, data);
+        }
+
+        Ok(TarSource { entries })
+    }
+}
+
+#[cfg(feature = "tar-source")]
+impl AssetSource for TarSource {
+    fn load(&self, id: &Path) -> io::Result<Vec<u8>> {
+        self.entries.load(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_source() {
+        let mut source = MemorySource::new();
+        source.insert("Ada.inx", vec![1, 2, 3]);
+
+        assert_eq!(source.load(Path::new("Ada.inx")).unwrap(), vec![1, 2, 3]);
+        assert_eq!(
+            source.load(Path::new("Aka.inx")).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[cfg(feature = "zip-source")]
+    #[test]
+    fn test_zip_source() {
+        use std::io::{Cursor, Write};
+
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .start_file("models/Ada.inx", zip::write::FileOptions::default())
+            .unwrap();
+        writer.write_all(b"puppet").unwrap();
+        let archive = writer.finish().unwrap();
+
+        let source = ZipSource::new(Cursor::new(archive.into_inner())).unwrap();
+        assert_eq!(source.load(Path::new("models/Ada.inx")).unwrap(), b"puppet");
+        assert_eq!(
+            source.load(Path::new("Ada.inx")).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[cfg(feature = "tar-source")]
+    #[test]
+    fn test_tar_source() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(6);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "models/Ada.inx", &b"puppet"[..])
+            .unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        let source = TarSource::new(&archive[..]).unwrap();
+        assert_eq!(source.load(Path::new("models/Ada.inx")).unwrap(), b"puppet");
+    }
+
+    #[cfg(feature = "tar-source")]
+    #[test]
+    fn test_tar_oversized_header() {
+        /* A header claiming a petabyte fails on the short body rather than allocating it */
+        let mut header = tar::Header::new_gnu();
+        header.set_path("models/Ada.inx").unwrap();
+        header.set_size(1 << 50);
+        header.set_cksum();
+        let mut archive = header.as_bytes().to_vec();
+        archive.extend_from_slice(&[0; 512]);
+
+        assert!(TarSource::new(&archive[..]).is_err());
+    }
+}
@@ -5,25 +5,136 @@
     Authors: Aki "lethalbit" Van Ness
 */
 
-use crate::puppet::Inochi2DPuppet;
-use crate::Result;
+use crate::asset::{AssetSource, FileSystemSource};
+use crate::format::LoadLimits;
+use crate::loader::PuppetLoader;
+use crate::puppet::{Inochi2DPuppet, LoadOptions, PuppetSource};
+use crate::reload::{HotReloadEvent, HotReloader};
+use crate::rights::RightsPolicy;
+use crate::{Inochi2DError, Result};
 
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[cfg(feature = "logging")]
 use tracing::debug;
 
-use crate::ffi::{inCleanup, inUpdate, inInit, inViewportGet, inViewportSet, types::InTimingFunc};
+use crate::ffi::{inCleanup, inInit, inUpdate, inViewportGet, inViewportSet, types::InTimingFunc};
 
 pub struct Inochi2D {
     pub puppets: Vec<Inochi2DPuppet>,
 
     pub view_width: i32,
     pub view_height: i32,
+
+    assets: Arc<dyn AssetSource>,
+    hot_reload: Option<HotReloader>,
+    load_options: LoadOptions,
+    timing: InTimingFunc,
 }
 
 impl Inochi2D {
-    /// Add a new puppet to the Inochi2D context.
+    /// Set the asset source `add_puppet` resolves puppets through.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let mut ctx = Inochi2D::new(/* ... */);
+    ///
+    /// ctx.set_asset_source(Arc::new(FileSystemSource::new("./puppets")));
+    ///
+    /// ~~~
+    ///
+    pub fn set_asset_source(&mut self, source: Arc<dyn AssetSource>) {
+        self.assets = source;
+    }
+
+    /// Get the asset source `add_puppet` resolves puppets through.
+    pub fn asset_source(&self) -> &Arc<dyn AssetSource> {
+        &self.assets
+    }
+
+    /// Set the usage-rights policy puppets added to the context are checked against.
+    ///
+    /// Puppets already in the context are not re-checked.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let mut ctx = Inochi2D::new(/* ... */);
+    ///
+    /// ctx.set_rights_policy(Some(RightsPolicy::default()));
+    ///
+    /// ~~~
+    ///
+    pub fn set_rights_policy(&mut self, policy: Option<RightsPolicy>) {
+        self.load_options.rights_policy = policy;
+    }
+
+    /// Get the usage-rights policy puppets added to the context are checked against.
+    pub fn rights_policy(&self) -> Option<&RightsPolicy> {
+        self.load_options.rights_policy.as_ref()
+    }
+
+    /// Set whether puppets added to the context are upgraded to the current schema before
+    /// loading, see `Inochi2DPuppet::from_bytes_migrated`.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let mut ctx = Inochi2D::new(/* ... */);
+    ///
+    /// ctx.set_auto_migrate(true);
+    ///
+    /// ~~~
+    ///
+    pub fn set_auto_migrate(&mut self, migrate: bool) {
+        self.load_options.migrate = migrate;
+    }
+
+    /// Get whether puppets added to the context are upgraded to the current schema.
+    pub fn auto_migrate(&self) -> bool {
+        self.load_options.migrate
+    }
+
+    /// Set the resource limits puppets added to the context must stay within, see
+    /// `format::check_limits`.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let mut ctx = Inochi2D::new(/* ... */);
+    ///
+    /// ctx.set_load_limits(Some(LoadLimits::default()));
+    ///
+    /// ~~~
+    ///
+    pub fn set_load_limits(&mut self, limits: Option<LoadLimits>) {
+        self.load_options.limits = limits;
+    }
+
+    /// Get the resource limits puppets added to the context must stay within.
+    pub fn load_limits(&self) -> Option<&LoadLimits> {
+        self.load_options.limits.as_ref()
+    }
+
+    /* Load `id` through `assets` with `options` */
+    fn load_asset(
+        assets: &dyn AssetSource,
+        id: &Path,
+        options: &LoadOptions,
+    ) -> Result<Inochi2DPuppet> {
+        let buffer = assets
+            .load(id)
+            .map_err(|err| Inochi2DError::from_asset_io(id, err))?;
+
+        Inochi2DPuppet::from_checked_bytes(&buffer, PuppetSource::Path(id.to_path_buf()), options)
+    }
+
+    /// Add a new puppet to the Inochi2D context, resolved through the current asset source.
     ///
     /// # Example
     ///
@@ -34,12 +145,224 @@ impl Inochi2D {
     ///
     /// ~~~
     ///
-    pub fn add_puppet(&mut self, puppet: PathBuf) -> Result<()> {
-        self.puppets.push(Inochi2DPuppet::new(puppet)?);
+    pub fn add_puppet(&mut self, puppet: impl AsRef<Path>) -> Result<()> {
+        let puppet = Self::load_asset(self.assets.as_ref(), puppet.as_ref(), &self.load_options)?;
+        self.watch(puppet.source());
+        self.puppets.push(puppet);
 
         Ok(())
     }
 
+    /// Start loading a puppet on a background thread, resolved through the current asset source.
+    ///
+    /// Pass the returned loader to `poll_puppet_loader` from the render loop to add the
+    /// puppet to the context once it has been read. The loader keeps the rights policy, load
+    /// limits and migration setting the context has when it is spawned.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let mut ctx = Inochi2D::new(/* ... */);
+    ///
+    /// let mut loader = ctx.load_puppet_background("./puppets/Ada.inx");
+    ///
+    /// ~~~
+    ///
+    pub fn load_puppet_background(&self, puppet: impl AsRef<Path>) -> PuppetLoader {
+        PuppetLoader::spawn_with(self.assets.clone(), puppet, self.load_options.clone())
+    }
+
+    /// Check on a background puppet load, adding the puppet to the context when it is ready.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let mut ctx = Inochi2D::new(/* ... */);
+    /// let mut loader = ctx.load_puppet_background("./puppets/Ada.inx");
+    ///
+    /// while !ctx.poll_puppet_loader(&mut loader)? {
+    ///     println!("{:?}", loader.progress());
+    /// }
+    ///
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// - `true` if the puppet has been added to the context.
+    /// - `false` if the puppet is still loading.
+    /// - If loading failed an `Inochi2DError` describing the error will be returned.
+    ///
+    pub fn poll_puppet_loader(&mut self, loader: &mut PuppetLoader) -> Result<bool> {
+        match loader.poll() {
+            Some(puppet) => {
+                let puppet = puppet?;
+                self.watch(puppet.source());
+                self.puppets.push(puppet);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Add a new puppet to the Inochi2D context from an in-memory buffer.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let mut ctx = Inochi2D::new(/* ... */);
+    ///
+    /// ctx.add_puppet_bytes(include_bytes!("./puppets/Ada.inx"), Some("Ada".into()));
+    ///
+    /// ~~~
+    ///
+    pub fn add_puppet_bytes(&mut self, buffer: &[u8], name: Option<String>) -> Result<()> {
+        self.puppets.push(Inochi2DPuppet::from_checked_bytes(
+            buffer,
+            PuppetSource::Memory(name),
+            &self.load_options,
+        )?);
+
+        Ok(())
+    }
+
+    /// Add a new puppet to the Inochi2D context from a reader.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let mut ctx = Inochi2D::new(/* ... */);
+    ///
+    /// ctx.add_puppet_reader(File::open("./puppets/Ada.inx")?, Some("Ada".into()));
+    ///
+    /// ~~~
+    ///
+    pub fn add_puppet_reader<R: Read>(
+        &mut self,
+        mut reader: R,
+        name: Option<String>,
+    ) -> Result<()> {
+        let mut buffer = Vec::new();
+        match &self.load_options.limits {
+            /* Stop one byte past the limit so oversized streams are never read in full */
+            Some(limits) => reader
+                .take(limits.max_file_size as u64 + 1)
+                .read_to_end(&mut buffer)?,
+            None => reader.read_to_end(&mut buffer)?,
+        };
+
+        self.add_puppet_bytes(&buffer, name)
+    }
+
+    /// Enable reloading puppets when their files change.
+    ///
+    /// Changes are only detected for puppets loaded through the context's asset source, and
+    /// only if that source reports modification times. Files are checked at most once per
+    /// `interval` when `poll_hot_reload` is called.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let mut ctx = Inochi2D::new(/* ... */);
+    ///
+    /// ctx.enable_hot_reload(Duration::from_millis(500));
+    ///
+    /// ~~~
+    ///
+    pub fn enable_hot_reload(&mut self, interval: Duration) {
+        #[cfg(feature = "logging")]
+        debug!("Enabling hot-reload every {:?}", interval);
+        self.hot_reload = Some(HotReloader::new(interval));
+
+        /* Record the current state so only changes from here on trigger a reload */
+        let sources = self
+            .puppets
+            .iter()
+            .map(|p| p.source().clone())
+            .collect::<Vec<_>>();
+        for source in sources.iter() {
+            self.watch(source);
+        }
+    }
+
+    /* Record the modification time of a puppet's file, so its next change triggers a reload */
+    fn watch(&mut self, source: &PuppetSource) {
+        if let (Some(reloader), PuppetSource::Path(id)) = (self.hot_reload.as_mut(), source) {
+            if let Some(modified) = self.assets.modified(id) {
+                reloader.changed(id, modified);
+            }
+        }
+    }
+
+    /// Disable reloading puppets when their files change.
+    pub fn disable_hot_reload(&mut self) {
+        self.hot_reload = None;
+    }
+
+    /// Reload any puppets whose files changed since the last check.
+    ///
+    /// Reloaded puppets keep their position in `puppets`, their parameter values, mixer
+    /// layers and expressions. If reloading fails the old puppet is kept and the error is
+    /// reported. Does nothing unless `enable_hot_reload` was called.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let mut ctx = Inochi2D::new(/* ... */);
+    ///
+    /// for event in ctx.poll_hot_reload() {
+    ///     println!("{:?}", event);
+    /// }
+    ///
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// A `HotReloadEvent` for each puppet that was reloaded or failed to reload.
+    ///
+    pub fn poll_hot_reload(&mut self) -> Vec<HotReloadEvent> {
+        let mut events = Vec::new();
+        let reloader = match self.hot_reload.as_mut() {
+            Some(reloader) => reloader,
+            None => return events,
+        };
+
+        if !reloader.due(Instant::now()) {
+            return events;
+        }
+
+        /* Check each file once, several puppets may have been loaded from the same one */
+        let mut changed = HashMap::new();
+        for p in self.puppets.iter() {
+            if let PuppetSource::Path(id) = p.source() {
+                if !changed.contains_key(id) {
+                    let modified = self.assets.modified(id);
+                    let is_changed = modified.is_some_and(|time| reloader.changed(id, time));
+                    changed.insert(id.clone(), is_changed);
+                }
+            }
+        }
+
+        for (index, p) in self.puppets.iter_mut().enumerate() {
+            let id = match p.source() {
+                PuppetSource::Path(id) if changed[id] => id.clone(),
+                _ => continue,
+            };
+
+            #[cfg(feature = "logging")]
+            debug!("Reloading puppet {}", id.display());
+            match Self::load_asset(self.assets.as_ref(), &id, &self.load_options) {
+                Ok(mut puppet) => {
+                    puppet.carry_over(p);
+                    *p = puppet;
+                    events.push(HotReloadEvent::Reloaded { index, id });
+                }
+                Err(error) => events.push(HotReloadEvent::Failed { index, id, error }),
+            }
+        }
+
+        events
+    }
+
     /// Update all puppets in the current context.
     ///
     /// # Example
@@ -131,8 +454,6 @@ impl Inochi2D {
         (viewport_width, viewport_height)
     }
 
-
-
     /// Update current Inochi2D context
     ///
     /// # Example
@@ -147,6 +468,20 @@ impl Inochi2D {
         }
     }
 
+    /// Get the timing function the context was created with.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let ctx = Inochi2D::new(/* ... */);
+    ///
+    /// let filters = ParamFilters::new(ctx.timing_function());
+    /// ~~~
+    ///
+    pub fn timing_function(&self) -> extern "C" fn() -> f64 {
+        self.timing
+    }
+
     /// Initialize a new Inochi2D context.
     ///
     /// # Example
@@ -173,6 +508,11 @@ impl Inochi2D {
 
                 view_width: w,
                 view_height: h,
+
+                assets: Arc::new(FileSystemSource::default()),
+                hot_reload: None,
+                load_options: LoadOptions::default(),
+                timing,
             }
         }
     }
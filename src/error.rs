@@ -0,0 +1,131 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::Utf8Error;
+
+use crate::format::FormatError;
+use crate::puppet::PuppetSource;
+use crate::rights::RightsViolation;
+
+/// Errors returned by the Inochi2D bindings.
+#[derive(Debug)]
+pub enum Inochi2DError {
+    /// The Inochi2D runtime failed to load a puppet.
+    Load {
+        /// Where the puppet was being loaded from.
+        source: PuppetSource,
+        /// The message reported by `inErrorGet`.
+        message: String,
+    },
+    /// The error message reported by Inochi2D was not valid UTF-8.
+    Utf8(Utf8Error),
+    /// Inochi2D returned a null handle without reporting an error.
+    NullHandle(PuppetSource),
+    /// A null buffer was given to `Inochi2DPuppet::from_raw_parts`.
+    NullBuffer,
+    /// `Inochi2DBuilder::build` was called without a timing function.
+    MissingTimingFunction,
+    /// The given path does not point to a loadable file.
+    InvalidPath(PathBuf),
+    /// Reading puppet data failed.
+    Io(io::Error),
+    /// The puppet container could not be parsed.
+    Format(FormatError),
+    /// The puppet's usage rights conflict with the context's `RightsPolicy`.
+    Rights {
+        source: PuppetSource,
+        violations: Vec<RightsViolation>,
+    },
+    /// A 1D value was given for a 2D parameter, or the other way around.
+    ParamDimension {
+        /// Name of the parameter.
+        name: String,
+        /// Whether the parameter is 2D.
+        is_vec2: bool,
+    },
+}
+
+impl fmt::Display for Inochi2DError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Inochi2DError::Load { source, message } => {
+                write!(f, "Unable to load puppet from {}: {}", source, message)
+            }
+            Inochi2DError::Utf8(_) => write!(f, "Unable to decode Inochi2D error message"),
+            Inochi2DError::NullHandle(source) => write!(
+                f,
+                "Unknown error loading puppet from {} (are C bindings valid?)",
+                source
+            ),
+            Inochi2DError::NullBuffer => write!(f, "Unable to load puppet from a null buffer"),
+            Inochi2DError::MissingTimingFunction => {
+                write!(f, "timing must be called before build!")
+            }
+            Inochi2DError::InvalidPath(path) => write!(f, "Invalid path {}", path.display()),
+            Inochi2DError::Io(err) => write!(f, "Unable to read puppet data: {}", err),
+            Inochi2DError::Format(err) => write!(f, "Invalid puppet data: {}", err),
+            Inochi2DError::Rights { source, violations } => {
+                write!(f, "Usage rights of {} do not permit this use", source)?;
+                for (i, violation) in violations.iter().enumerate() {
+                    write!(f, "{} {}", if i == 0 { ":" } else { "," }, violation)?;
+                }
+                Ok(())
+            }
+            Inochi2DError::ParamDimension { name, is_vec2 } => write!(
+                f,
+                "Parameter {} is {}, but a {} value was given",
+                name,
+                if *is_vec2 { "2D" } else { "1D" },
+                if *is_vec2 { "1D" } else { "2D" }
+            ),
+        }
+    }
+}
+
+impl Inochi2DError {
+    /* Map an error from an asset source, treating missing assets as invalid paths */
+    pub(crate) fn from_asset_io(id: &Path, err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::NotFound {
+            Inochi2DError::InvalidPath(id.to_path_buf())
+        } else {
+            Inochi2DError::Io(err)
+        }
+    }
+}
+
+impl Error for Inochi2DError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Inochi2DError::Utf8(err) => Some(err),
+            Inochi2DError::Io(err) => Some(err),
+            Inochi2DError::Format(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<Utf8Error> for Inochi2DError {
+    fn from(err: Utf8Error) -> Self {
+        Inochi2DError::Utf8(err)
+    }
+}
+
+impl From<io::Error> for Inochi2DError {
+    fn from(err: io::Error) -> Self {
+        Inochi2DError::Io(err)
+    }
+}
+
+impl From<FormatError> for Inochi2DError {
+    fn from(err: FormatError) -> Self {
+        Inochi2DError::Format(err)
+    }
+}
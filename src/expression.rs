@@ -0,0 +1,349 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "logging")]
+use tracing::debug;
+
+use crate::mixer::{BlendMode, ParamMixer};
+use crate::param::ParamValue;
+use crate::puppet::Inochi2DPuppet;
+
+/// Name of the vendor data section expressions are embedded in.
+pub const EXPRESSIONS_VENDOR_NAME: &str = "inochi2d-rs.expressions";
+
+/// Mixer priority of the layers `ExpressionPlayer` adds, above tracking at 0.
+pub const EXPRESSION_LAYER_PRIORITY: i32 = 50;
+
+/* Mixer layer names are prefixed so expressions don't clash with other layers */
+const LAYER_PREFIX: &str = "expression:";
+
+/// A named set of parameter values, such as "happy" or "shocked".
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Expression {
+    pub name: String,
+    /// The value of each parameter in the expression, by parameter name.
+    pub values: BTreeMap<String, ParamValue>,
+}
+
+/// The expressions of a puppet.
+///
+/// # Example
+///
+/// ~~~no_run
+/// let happy = puppet.capture_expression("happy", &["MouthSmile", "EyeSmile", "BrowY"]);
+/// puppet.expressions_mut().insert(happy);
+///
+/// std::fs::write("./Ada.expressions.json", puppet.expressions().to_json()?)?;
+/// ~~~
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExpressionSet {
+    /* In the order they were added, for hotkey lists */
+    expressions: Vec<Expression>,
+}
+
+impl ExpressionSet {
+    /// Create an empty expression set.
+    pub fn new() -> Self {
+        ExpressionSet::default()
+    }
+
+    /// Read an expression set from JSON.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Serialize the expression set to JSON.
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+
+    /// Add an expression, replacing and returning any expression with the same name.
+    pub fn insert(&mut self, expression: Expression) -> Option<Expression> {
+        match self
+            .expressions
+            .iter_mut()
+            .find(|current| current.name == expression.name)
+        {
+            Some(current) => Some(std::mem::replace(current, expression)),
+            None => {
+                self.expressions.push(expression);
+                None
+            }
+        }
+    }
+
+    /// Get the expression called `name`.
+    pub fn get(&self, name: &str) -> Option<&Expression> {
+        self.expressions
+            .iter()
+            .find(|expression| expression.name == name)
+    }
+
+    /// Remove the expression called `name`.
+    pub fn remove(&mut self, name: &str) -> Option<Expression> {
+        let index = self
+            .expressions
+            .iter()
+            .position(|expression| expression.name == name)?;
+
+        Some(self.expressions.remove(index))
+    }
+
+    /// Get the expressions, in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = &Expression> {
+        self.expressions.iter()
+    }
+
+    /// Check whether the set has no expressions.
+    pub fn is_empty(&self) -> bool {
+        self.expressions.is_empty()
+    }
+}
+
+/* A change of an expression layer's weight over time */
+#[derive(Debug, Clone)]
+struct Fade {
+    layer: String,
+    from: f32,
+    to: f32,
+    start: f64,
+    duration: f32,
+}
+
+/// Fades expressions of a puppet in and out, using a layer of its `ParamMixer` for each
+/// playing expression.
+///
+/// Use one player per puppet, and call `update` every frame before `Inochi2DPuppet::update`.
+///
+/// # Example
+///
+/// ~~~no_run
+/// let mut player = ExpressionPlayer::new(ctx.timing_function());
+///
+/// loop {
+///     let puppet = &mut ctx.puppets[0];
+///     if hotkeys.pressed(Key::F1) {
+///         player.switch(puppet, "happy", 0.25);
+///     }
+///
+///     player.update(puppet);
+///     puppet.update();
+/// }
+/// ~~~
+///
+pub struct ExpressionPlayer {
+    timing: extern "C" fn() -> f64,
+    fades: Vec<Fade>,
+}
+
+impl ExpressionPlayer {
+    /// Create a player that reads the time from `timing`, usually `Inochi2D::timing_function`.
+    pub fn new(timing: extern "C" fn() -> f64) -> Self {
+        ExpressionPlayer {
+            timing,
+            fades: Vec::new(),
+        }
+    }
+
+    /// Fade the expression called `name` to `weight` over `fade` seconds.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the puppet has no expression called `name`.
+    ///
+    pub fn play(
+        &mut self,
+        puppet: &mut Inochi2DPuppet,
+        name: &str,
+        weight: f32,
+        fade: f32,
+    ) -> bool {
+        let expression = match puppet.expressions().get(name) {
+            Some(expression) => expression.clone(),
+            None => return false,
+        };
+        let now = (self.timing)();
+
+        self.play_at(puppet.mixer_mut(), &expression, weight, fade, now);
+        true
+    }
+
+    /// Fade the expression called `name` out over `fade` seconds.
+    pub fn stop(&mut self, puppet: &mut Inochi2DPuppet, name: &str, fade: f32) {
+        let now = (self.timing)();
+
+        self.stop_at(puppet.mixer_mut(), name, fade, now);
+    }
+
+    /// Fade the expression called `name` in fully and every other expression out, both over
+    /// `fade` seconds.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the puppet has no expression called `name`, nothing is changed then.
+    ///
+    pub fn switch(&mut self, puppet: &mut Inochi2DPuppet, name: &str, fade: f32) -> bool {
+        if puppet.expressions().get(name).is_none() {
+            return false;
+        }
+
+        let now = (self.timing)();
+        for playing in self.playing(puppet.mixer()) {
+            if playing != name {
+                self.stop_at(puppet.mixer_mut(), &playing, fade, now);
+            }
+        }
+
+        self.play(puppet, name, 1.0, fade)
+    }
+
+    /// Advance the fades, removing the layers of expressions that have faded out.
+    pub fn update(&mut self, puppet: &mut Inochi2DPuppet) {
+        let now = (self.timing)();
+
+        self.update_at(puppet.mixer_mut(), now);
+    }
+
+    /* Names of the expressions with a layer in `mixer` */
+    fn playing(&self, mixer: &ParamMixer) -> Vec<String> {
+        mixer
+            .layers()
+            .filter_map(|layer| layer.name().strip_prefix(LAYER_PREFIX))
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn fade(&mut self, mixer: &mut ParamMixer, layer: String, to: f32, duration: f32, now: f64) {
+        let from = mixer.layer(&layer).map_or(0.0, |layer| layer.weight());
+
+        self.fades.retain(|fade| fade.layer != layer);
+        self.fades.push(Fade {
+            layer,
+            from,
+            to: to.clamp(0.0, 1.0),
+            start: now,
+            duration: duration.max(0.0),
+        });
+        self.update_at(mixer, now);
+    }
+
+    fn play_at(
+        &mut self,
+        mixer: &mut ParamMixer,
+        expression: &Expression,
+        weight: f32,
+        fade: f32,
+        now: f64,
+    ) {
+        #[cfg(feature = "logging")]
+        debug!("Playing expression {} at {}", expression.name, weight);
+        let name = format!("{}{}", LAYER_PREFIX, expression.name);
+        if mixer.layer(&name).is_none() {
+            mixer
+                .add_layer(&name, BlendMode::Override, EXPRESSION_LAYER_PRIORITY)
+                .set_weight(0.0);
+        }
+
+        let layer = mixer.layer_mut(&name).unwrap();
+        layer.clear();
+        for (param, value) in expression.values.iter() {
+            layer.set(param, *value);
+        }
+
+        self.fade(mixer, name, weight, fade, now);
+    }
+
+    fn stop_at(&mut self, mixer: &mut ParamMixer, name: &str, fade: f32, now: f64) {
+        let name = format!("{}{}", LAYER_PREFIX, name);
+        if mixer.layer(&name).is_some() {
+            self.fade(mixer, name, 0.0, fade, now);
+        }
+    }
+
+    fn update_at(&mut self, mixer: &mut ParamMixer, now: f64) {
+        self.fades.retain(|fade| {
+            let t = if fade.duration > 0.0 {
+                ((now - fade.start) as f32 / fade.duration).clamp(0.0, 1.0)
+            } else {
+                1.0
+            };
+            let weight = fade.from + (fade.to - fade.from) * t;
+
+            match mixer.layer_mut(&fade.layer) {
+                Some(_) if t >= 1.0 && weight <= 0.0 => {
+                    mixer.remove_layer(&fade.layer);
+                }
+                Some(layer) => layer.set_weight(weight),
+                None => return false,
+            }
+
+            t < 1.0
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn timing() -> f64 {
+        0.0
+    }
+
+    fn expression(name: &str, value: f32) -> Expression {
+        Expression {
+            name: name.into(),
+            values: [
+                ("MouthSmile".to_string(), ParamValue::Scalar(value)),
+                ("Eyes".to_string(), ParamValue::Vec2(value, -value)),
+            ]
+            .into_iter()
+            .collect(),
+        }
+    }
+
+    #[test]
+    fn test_json() {
+        let mut set = ExpressionSet::new();
+        set.insert(expression("happy", 1.0));
+        set.insert(expression("angry", -1.0));
+        assert!(set.insert(expression("happy", 0.5)).is_some());
+
+        let json = set.to_json().unwrap();
+        assert_eq!(
+            json,
+            concat!(
+                r#"{"expressions":[{"name":"happy","values":{"Eyes":[0.5,-0.5],"MouthSmile":0.5}},"#,
+                r#"{"name":"angry","values":{"Eyes":[-1.0,1.0],"MouthSmile":-1.0}}]}"#
+            )
+        );
+        assert_eq!(ExpressionSet::from_json(&json).unwrap(), set);
+    }
+
+    #[test]
+    fn test_fade() {
+        let mut mixer = ParamMixer::new();
+        let mut player = ExpressionPlayer::new(timing);
+        let smile = |mixer: &ParamMixer| mixer.resolve(|_| Some((0.0, 0.0)))["MouthSmile"].0;
+
+        player.play_at(&mut mixer, &expression("happy", 1.0), 1.0, 1.0, 0.0);
+        player.update_at(&mut mixer, 0.5);
+        assert_eq!(smile(&mixer), 0.5);
+
+        /* Switching fades the other expression out and removes its layer */
+        player.play_at(&mut mixer, &expression("angry", -1.0), 1.0, 0.5, 1.0);
+        player.stop_at(&mut mixer, "happy", 0.5, 1.0);
+        player.update_at(&mut mixer, 2.0);
+        assert_eq!(smile(&mixer), -1.0);
+        assert_eq!(player.playing(&mixer), vec!["angry".to_string()]);
+    }
+}
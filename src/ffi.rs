@@ -32,6 +32,9 @@ pub mod types {
     create_opaque_type!(InCamera);
     pub type InCameraPtr = *mut InCamera;
 
+    create_opaque_type!(InParameter);
+    pub type InParameterPtr = *mut InParameter;
+
     create_opaque_type!(InRenderable);
     pub type InRenderablePtr = *mut InRenderable;
 
@@ -70,11 +73,25 @@ extern "C" {
 
     /* Puppets */
     pub fn inPuppetLoad(path: *const u8) -> types::InPuppetPtr;
-    pub fn inPuppetLoadEx(path: *const u8, len: usize) -> types::InPuppetPtr;
     pub fn inPuppetLoadFromMemory(data: *const u8, len: usize) -> types::InPuppetPtr;
     pub fn inPuppetDestroy(puppet: types::InPuppetPtr);
-    pub fn inPuppetGetName(puppet: types::InPuppetPtr, name: *const u8, len: *const usize);
+    pub fn inPuppetGetName(puppet: types::InPuppetPtr, name: *mut *const u8, len: *mut usize);
     pub fn inPuppetUpdate(puppet: types::InPuppetPtr);
     #[cfg(feature = "opengl")]
     pub fn inPuppetDraw(puppet: types::InPuppetPtr);
+    /* Only the length is written if `array_ptr` is null */
+    pub fn inPuppetGetParameters(
+        puppet: types::InPuppetPtr,
+        array_ptr: *mut *mut types::InParameterPtr,
+        length: *mut usize,
+    );
+
+    /* Parameters */
+    pub fn inParameterGetName(param: types::InParameterPtr) -> *const libc::c_char;
+    pub fn inParameterGetUUID(param: types::InParameterPtr) -> u32;
+    pub fn inParameterIsVec2(param: types::InParameterPtr) -> bool;
+    pub fn inParameterGetMin(param: types::InParameterPtr, x: *mut f32, y: *mut f32);
+    pub fn inParameterGetMax(param: types::InParameterPtr, x: *mut f32, y: *mut f32);
+    pub fn inParameterGetValue(param: types::InParameterPtr, x: *mut f32, y: *mut f32);
+    pub fn inParameterSetValue(param: types::InParameterPtr, x: f32, y: f32);
 }
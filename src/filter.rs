@@ -0,0 +1,308 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use crate::param::{Inochi2DParameterMut, ParamValue};
+use crate::Result;
+
+/// How values set through `ParamFilters` are smoothed.
+///
+/// All filters are defined in seconds or Hertz rather than per frame, so they behave the
+/// same at any frame rate.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Filter {
+    /// Values are set as given.
+    #[default]
+    None,
+    /// Exponential smoothing, moving 63% of the way to the target every `time_constant`
+    /// seconds.
+    Exponential { time_constant: f32 },
+    /// A critically damped spring, which follows the target without overshooting and
+    /// settles in roughly `smooth_time` seconds.
+    Spring { smooth_time: f32 },
+    /// The One Euro filter, which smooths heavily while the input is slow and follows it
+    /// closely while it moves fast.
+    ///
+    /// `min_cutoff` is the cutoff frequency in Hertz at rest, `beta` how much the cutoff
+    /// rises with speed and `derivative_cutoff` the cutoff used to estimate the speed.
+    OneEuro {
+        min_cutoff: f32,
+        beta: f32,
+        derivative_cutoff: f32,
+    },
+}
+
+impl Filter {
+    /// The One Euro filter with parameters suited to face tracking.
+    pub fn one_euro() -> Self {
+        Filter::OneEuro {
+            min_cutoff: 1.0,
+            beta: 0.5,
+            derivative_cutoff: 1.0,
+        }
+    }
+}
+
+/* Smoothing factor of a low-pass filter with `cutoff` Hertz */
+fn low_pass_alpha(cutoff: f32, dt: f32) -> f32 {
+    let tau = 1.0 / (2.0 * PI * cutoff.max(f32::EPSILON));
+    1.0 / (1.0 + tau / dt)
+}
+
+/* Filter state of a single axis */
+#[derive(Debug, Clone, Copy, Default)]
+struct Axis {
+    value: f32,
+    /* Velocity of the spring, or the smoothed derivative for One Euro */
+    velocity: f32,
+    raw: f32,
+}
+
+impl Axis {
+    fn new(value: f32) -> Self {
+        Axis {
+            value,
+            velocity: 0.0,
+            raw: value,
+        }
+    }
+
+    fn step(&mut self, filter: Filter, target: f32, dt: f32) -> f32 {
+        match filter {
+            Filter::None => self.value = target,
+            Filter::Exponential { time_constant } => {
+                let alpha = 1.0 - (-dt / time_constant.max(f32::EPSILON)).exp();
+                self.value += (target - self.value) * alpha;
+            }
+            Filter::Spring { smooth_time } => {
+                /* Exact solution of a critically damped spring over `dt` */
+                let omega = 2.0 / smooth_time.max(f32::EPSILON);
+                let offset = self.value - target;
+                let c = self.velocity + omega * offset;
+                let decay = (-omega * dt).exp();
+
+                self.value = target + (offset + c * dt) * decay;
+                self.velocity = (c - omega * (offset + c * dt)) * decay;
+            }
+            Filter::OneEuro {
+                min_cutoff,
+                beta,
+                derivative_cutoff,
+            } => {
+                let derivative = (target - self.raw) / dt;
+                self.velocity +=
+                    (derivative - self.velocity) * low_pass_alpha(derivative_cutoff, dt);
+
+                let cutoff = min_cutoff + beta * self.velocity.abs();
+                self.value += (target - self.value) * low_pass_alpha(cutoff, dt);
+            }
+        }
+
+        self.raw = target;
+        self.value
+    }
+}
+
+/* Filter settings and state of one parameter */
+#[derive(Debug, Clone)]
+struct ParamFilter {
+    /* `None` to use the default filter */
+    filter: Option<Filter>,
+    axes: Option<[Axis; 2]>,
+    last: f64,
+}
+
+/// Smoothing filters between tracking input and parameters, configured per parameter.
+///
+/// Each parameter is filtered by the time passed since it was last set, as reported by the
+/// timing function, so filters behave the same at any frame rate.
+///
+/// # Example
+///
+/// ~~~no_run
+/// let mut filters = ParamFilters::new(ctx.timing_function());
+/// filters.set_default_filter(Filter::one_euro());
+/// filters.set_filter("MouthOpen", Filter::Exponential { time_constant: 0.05 });
+///
+/// loop {
+///     let puppet = &mut ctx.puppets[0];
+///     if let Some(mut head) = puppet.param_mut("HeadX") {
+///         filters.apply(&mut head, ParamValue::Scalar(tracking.yaw))?;
+///     }
+///     puppet.update();
+/// }
+/// ~~~
+///
+pub struct ParamFilters {
+    timing: extern "C" fn() -> f64,
+    default: Filter,
+    params: HashMap<String, ParamFilter>,
+}
+
+impl ParamFilters {
+    /// Create filters that read the time from `timing`, usually `Inochi2D::timing_function`.
+    pub fn new(timing: extern "C" fn() -> f64) -> Self {
+        ParamFilters {
+            timing,
+            default: Filter::None,
+            params: HashMap::new(),
+        }
+    }
+
+    /// Set the filter used for parameters without one of their own.
+    pub fn set_default_filter(&mut self, filter: Filter) {
+        self.default = filter;
+    }
+
+    /// Set the filter of the parameter called `name`.
+    pub fn set_filter(&mut self, name: &str, filter: Filter) {
+        let param = self.entry(name);
+        param.filter = Some(filter);
+        param.axes = None;
+    }
+
+    /// Forget the filter state of every parameter, so the next values are taken as given.
+    pub fn reset(&mut self) {
+        for param in self.params.values_mut() {
+            param.axes = None;
+        }
+    }
+
+    fn entry(&mut self, name: &str) -> &mut ParamFilter {
+        if !self.params.contains_key(name) {
+            self.params.insert(
+                name.to_string(),
+                ParamFilter {
+                    filter: None,
+                    axes: None,
+                    last: 0.0,
+                },
+            );
+        }
+
+        self.params.get_mut(name).unwrap()
+    }
+
+    /// Filter `value` for the parameter called `name`, at the current time.
+    pub fn filter(&mut self, name: &str, value: ParamValue) -> ParamValue {
+        let now = (self.timing)();
+
+        self.filter_at(name, value, now)
+    }
+
+    /// Filter `value` for the parameter called `name`, at `time` seconds.
+    ///
+    /// The first value given for a parameter is passed through unchanged.
+    ///
+    pub fn filter_at(&mut self, name: &str, value: ParamValue, time: f64) -> ParamValue {
+        let default = self.default;
+        let param = self.entry(name);
+        let filter = param.filter.unwrap_or(default);
+        let (x, y) = value.pair();
+        let dt = (time - param.last) as f32;
+
+        let axes = match &mut param.axes {
+            Some(axes) if dt > 0.0 => axes,
+            /* No time has passed, keep the last output */
+            Some(axes) => {
+                return ParamValue::new(value.is_vec2(), (axes[0].value, axes[1].value));
+            }
+            None => {
+                param.axes = Some([Axis::new(x), Axis::new(y)]);
+                param.last = time;
+                return value;
+            }
+        };
+
+        let filtered = (axes[0].step(filter, x, dt), axes[1].step(filter, y, dt));
+        param.last = time;
+
+        ParamValue::new(value.is_vec2(), filtered)
+    }
+
+    /// Filter `value` and set it on `param`, see `Inochi2DParameterMut::set`.
+    pub fn apply(&mut self, param: &mut Inochi2DParameterMut<'_>, value: ParamValue) -> Result<()> {
+        let value = self.filter(param.name(), value);
+
+        param.set(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn timing() -> f64 {
+        0.0
+    }
+
+    /* Step from 0 towards 1 for one second at `fps`, returning the final value */
+    fn run(filter: Filter, fps: u32) -> f32 {
+        let mut filters = ParamFilters::new(timing);
+        filters.set_filter("HeadX", filter);
+        filters.filter_at("HeadX", ParamValue::Scalar(0.0), 0.0);
+
+        let mut value = ParamValue::Scalar(0.0);
+        for frame in 1..=fps {
+            value = filters.filter_at("HeadX", ParamValue::Scalar(1.0), frame as f64 / fps as f64);
+        }
+
+        value.pair().0
+    }
+
+    #[test]
+    fn test_frame_rate_independent() {
+        for filter in [
+            Filter::Exponential {
+                time_constant: 0.25,
+            },
+            Filter::Spring { smooth_time: 0.5 },
+        ] {
+            let (slow, fast) = (run(filter, 30), run(filter, 144));
+
+            assert!(slow > 0.5 && slow < 1.0, "{:?} reached {}", filter, slow);
+            assert!(
+                (slow - fast).abs() < 1e-3,
+                "{:?}: {} != {}",
+                filter,
+                slow,
+                fast
+            );
+        }
+        assert_eq!(run(Filter::None, 30), 1.0);
+    }
+
+    #[test]
+    fn test_one_euro() {
+        let mut filters = ParamFilters::new(timing);
+        filters.set_default_filter(Filter::one_euro());
+
+        /* Jitter around 0.5 is smoothed out */
+        let mut value = 0.0;
+        for frame in 0..120 {
+            let jitter = if frame % 2 == 0 { 0.05 } else { -0.05 };
+            value = filters
+                .filter_at(
+                    "HeadX",
+                    ParamValue::Scalar(0.5 + jitter),
+                    frame as f64 / 60.0,
+                )
+                .pair()
+                .0;
+        }
+        assert!((value - 0.5).abs() < 0.02, "jitter left {}", value);
+
+        /* Two dimensional parameters keep both axes */
+        assert!(matches!(
+            filters.filter_at("Head", ParamValue::Vec2(1.0, 2.0), 0.0),
+            ParamValue::Vec2(..)
+        ));
+    }
+}
@@ -0,0 +1,302 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+use std::fmt;
+
+use serde_json::Value;
+
+use super::{reader, Container, FormatError};
+use crate::texture::encoded_dimensions;
+
+/// A resource limit of `LoadLimits`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Limit {
+    /// Size of the whole container in bytes.
+    FileSize,
+    /// Nesting depth of objects and arrays in the puppet JSON.
+    JsonDepth,
+    /// Number of nodes in the node tree.
+    Nodes,
+    /// Number of mesh vertices over all nodes.
+    Vertices,
+    /// Number of entries in the texture section.
+    Textures,
+    /// Width or height of a single texture.
+    TextureDimension,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::FileSize => write!(f, "file size"),
+            Limit::JsonDepth => write!(f, "JSON nesting depth"),
+            Limit::Nodes => write!(f, "node count"),
+            Limit::Vertices => write!(f, "vertex count"),
+            Limit::Textures => write!(f, "texture count"),
+            Limit::TextureDimension => write!(f, "texture dimension"),
+        }
+    }
+}
+
+/// Resource limits for puppet data from untrusted sources.
+///
+/// The limits are checked by the Rust parser before anything is handed to Inochi2D, so
+/// oversized or malicious puppets are refused with `FormatError::LimitExceeded` rather than
+/// exhausting memory in the runtime.
+///
+/// # Example
+///
+/// ~~~no_run
+/// let limits = LoadLimits {
+///     max_file_size: 16 * 1024 * 1024,
+///     ..LoadLimits::default()
+/// };
+///
+/// let container = Container::parse_with_limits(&upload, &limits)?;
+/// ~~~
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadLimits {
+    /// Maximum size of the whole container in bytes.
+    pub max_file_size: usize,
+    /// Maximum nesting depth of objects and arrays in the puppet JSON.
+    pub max_json_depth: usize,
+    /// Maximum number of nodes in the node tree.
+    pub max_nodes: usize,
+    /// Maximum number of mesh vertices over all nodes.
+    pub max_vertices: usize,
+    /// Maximum number of entries in the texture section.
+    pub max_textures: usize,
+    /// Maximum width or height of a single texture.
+    pub max_texture_dimension: u32,
+}
+
+impl Default for LoadLimits {
+    /// Limits generous enough for any hand-made puppet.
+    fn default() -> Self {
+        LoadLimits {
+            max_file_size: 256 * 1024 * 1024,
+            max_json_depth: 64,
+            max_nodes: 10_000,
+            max_vertices: 1_000_000,
+            max_textures: 256,
+            max_texture_dimension: 16384,
+        }
+    }
+}
+
+fn exceeded(limit: Limit, value: usize, max: usize) -> Result<(), FormatError> {
+    if value > max {
+        Err(FormatError::LimitExceeded { limit, value, max })
+    } else {
+        Ok(())
+    }
+}
+
+/* Check the nesting depth of `json` without parsing it, so deep input never reaches serde */
+fn json_depth(json: &str, max: usize) -> Result<(), FormatError> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for byte in json.bytes() {
+        match byte {
+            _ if escaped => escaped = false,
+            b'\\' if in_string => escaped = true,
+            b'"' => in_string = !in_string,
+            _ if in_string => (),
+            b'{' | b'[' => {
+                depth += 1;
+                exceeded(Limit::JsonDepth, depth, max)?;
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+/* Count the nodes and mesh vertices in the node tree */
+fn count_nodes(node: &Value, nodes: &mut usize, vertices: &mut usize) {
+    *nodes += 1;
+    if let Some(Value::Array(verts)) = node.get("mesh").and_then(|mesh| mesh.get("verts")) {
+        *vertices += verts.len() / 2;
+    }
+
+    if let Some(Value::Array(children)) = node.get("children") {
+        for child in children {
+            count_nodes(child, nodes, vertices);
+        }
+    }
+}
+
+/// Check puppet container data against `limits` without copying its textures.
+///
+/// Only the headers of textures are read to find their dimensions, so this is cheap enough
+/// to run on every upload before `Container::parse` or `Inochi2DPuppet::from_bytes`.
+///
+/// # Example
+///
+/// ~~~no_run
+/// format::check_limits(&upload, &LoadLimits::default())?;
+///
+/// let puppet = Inochi2DPuppet::from_bytes(&upload, None)?;
+/// ~~~
+///
+/// # Returns
+///
+/// - `Ok(())` if the data is a container within the limits.
+/// - `FormatError::LimitExceeded` naming the first limit the data exceeds.
+/// - Otherwise a `FormatError` describing why the data could not be read.
+///
+pub fn check_limits(data: &[u8], limits: &LoadLimits) -> Result<(), FormatError> {
+    exceeded(Limit::FileSize, data.len(), limits.max_file_size)?;
+
+    let json = reader::json(data)?;
+    json_depth(json, limits.max_json_depth)?;
+
+    let doc: Value = serde_json::from_str(json).map_err(FormatError::Json)?;
+    let (mut nodes, mut vertices) = (0, 0);
+    if let Some(root) = doc.get("nodes") {
+        count_nodes(root, &mut nodes, &mut vertices);
+    }
+    exceeded(Limit::Nodes, nodes, limits.max_nodes)?;
+    exceeded(Limit::Vertices, vertices, limits.max_vertices)?;
+
+    exceeded(
+        Limit::Textures,
+        reader::texture_count(data)?,
+        limits.max_textures,
+    )?;
+    for (index, (encoding, texture)) in reader::texture_slices(data)?.into_iter().enumerate() {
+        if texture.is_empty() {
+            continue;
+        }
+
+        let (width, height) = encoded_dimensions(encoding, texture)
+            .map_err(|error| FormatError::Texture { index, error })?;
+        exceeded(
+            Limit::TextureDimension,
+            width.max(height) as usize,
+            limits.max_texture_dimension as usize,
+        )?;
+    }
+
+    Ok(())
+}
+
+impl Container {
+    /// Parse container data from an untrusted source, see `format::check_limits`.
+    pub fn parse_with_limits(data: &[u8], limits: &LoadLimits) -> Result<Self, FormatError> {
+        check_limits(data, limits)?;
+
+        Container::parse(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{Texture, TextureEncoding};
+    use crate::texture::{encode, AlphaMode, Image};
+
+    fn container(json: &str, size: u32) -> Vec<u8> {
+        let image = Image {
+            width: size,
+            height: size,
+            pixels: vec![0; size as usize * size as usize * 4],
+            alpha: AlphaMode::Straight,
+        };
+        let texture: Texture = encode(&image, TextureEncoding::Tga).unwrap();
+
+        Container {
+            json: json.into(),
+            textures: vec![texture],
+            vendor: Vec::new(),
+        }
+        .to_bytes()
+        .unwrap()
+    }
+
+    fn limit(data: &[u8], limits: &LoadLimits) -> Option<Limit> {
+        match check_limits(data, limits) {
+            Ok(()) => None,
+            Err(FormatError::LimitExceeded { limit, .. }) => Some(limit),
+            Err(err) => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let json = r#"{ "nodes": { "name": "Root", "children": [
+            { "name": "A", "mesh": { "verts": [0, 0, 1, 0, 0, 1] } },
+            { "name": "B \"[[[[\"", "children": [] }
+        ] } }"#;
+        let data = container(json, 32);
+        let limits = LoadLimits::default();
+
+        assert_eq!(limit(&data, &limits), None);
+        let cases = [
+            (
+                Limit::FileSize,
+                LoadLimits {
+                    max_file_size: data.len() - 1,
+                    ..limits.clone()
+                },
+            ),
+            (
+                Limit::JsonDepth,
+                LoadLimits {
+                    max_json_depth: 3,
+                    ..limits.clone()
+                },
+            ),
+            (
+                Limit::Nodes,
+                LoadLimits {
+                    max_nodes: 2,
+                    ..limits.clone()
+                },
+            ),
+            (
+                Limit::Vertices,
+                LoadLimits {
+                    max_vertices: 2,
+                    ..limits.clone()
+                },
+            ),
+            (
+                Limit::Textures,
+                LoadLimits {
+                    max_textures: 0,
+                    ..limits.clone()
+                },
+            ),
+            (
+                Limit::TextureDimension,
+                LoadLimits {
+                    max_texture_dimension: 16,
+                    ..limits.clone()
+                },
+            ),
+        ];
+        for (expected, limits) in cases {
+            assert_eq!(limit(&data, &limits), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_deep_json() {
+        let json = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+
+        assert_eq!(
+            limit(&container(&json, 1), &LoadLimits::default()),
+            Some(Limit::JsonDepth)
+        );
+    }
+}
@@ -0,0 +1,375 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+use std::fmt;
+
+use serde_json::{json, Map, Value};
+
+use super::model::{NO_TEXTURE, NO_THUMBNAIL};
+use super::FormatError;
+
+/* Number of texture slots parts have in the current schema: albedo, emission and bump map */
+const PART_TEXTURE_SLOTS: usize = 3;
+
+/// Revisions of the puppet JSON schema, oldest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SchemaVersion {
+    /// Masks share a single mode per node, stored as `masked_by` and `mask_mode`.
+    SharedMaskMode,
+    /// Parts have a single texture instead of albedo, emission and bump map slots.
+    SingleTexture,
+    /// The schema this crate reads and writes.
+    Current,
+}
+
+impl fmt::Display for SchemaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaVersion::SharedMaskMode => write!(f, "shared mask mode"),
+            SchemaVersion::SingleTexture => write!(f, "single texture"),
+            SchemaVersion::Current => write!(f, "current"),
+        }
+    }
+}
+
+/// A single change made by a migration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// `masked_by` and `mask_mode` were converted to `count` per-mask bindings.
+    MaskBindings { path: String, count: usize },
+    /// A part's texture slots were padded from `from` to three.
+    TextureSlots { path: String, from: usize },
+    /// `field` was missing and has been added with its default value.
+    AddedField { path: String, field: &'static str },
+    /// The obsolete `field` could not be converted and has been removed.
+    RemovedField { path: String, field: &'static str },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::MaskBindings { path, count } => {
+                write!(f, "{}: converted {} masks to per-mask modes", path, count)
+            }
+            Change::TextureSlots { path, from } => {
+                write!(f, "{}: padded {} texture slots to 3", path, from)
+            }
+            Change::AddedField { path, field } => write!(f, "{}: added default {}", path, field),
+            Change::RemovedField { path, field } => {
+                write!(f, "{}: removed obsolete {}", path, field)
+            }
+        }
+    }
+}
+
+/// What a migration did to a puppet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// The schema revision the puppet was in before migrating.
+    pub from: SchemaVersion,
+    pub changes: Vec<Change>,
+}
+
+impl MigrationReport {
+    /// Check whether the migration changed nothing.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/* Call `f` on every node with its path, depth first */
+fn visit_nodes(node: &mut Value, parent: &str, f: &mut impl FnMut(&mut Map<String, Value>, &str)) {
+    let node = match node.as_object_mut() {
+        Some(node) => node,
+        None => return,
+    };
+
+    let name = node.get("name").and_then(Value::as_str).unwrap_or("");
+    let path = if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent, name)
+    };
+
+    f(node, &path);
+
+    if let Some(Value::Array(children)) = node.get_mut("children") {
+        for child in children.iter_mut() {
+            visit_nodes(child, &path, f);
+        }
+    }
+}
+
+fn is_part(node: &Map<String, Value>) -> bool {
+    node.get("type").and_then(Value::as_str) == Some("Part")
+}
+
+fn detect(doc: &mut Value) -> SchemaVersion {
+    let mut version = SchemaVersion::Current;
+    if let Some(nodes) = doc.get_mut("nodes") {
+        visit_nodes(nodes, "", &mut |node, _| {
+            if node.contains_key("masked_by") || node.contains_key("mask_mode") {
+                version = version.min(SchemaVersion::SharedMaskMode);
+            }
+            let slots = node.get("textures").and_then(Value::as_array).map(Vec::len);
+            if is_part(node) && slots.is_some_and(|slots| slots < PART_TEXTURE_SLOTS) {
+                version = version.min(SchemaVersion::SingleTexture);
+            }
+        });
+    }
+
+    version
+}
+
+/* Convert `masked_by` and `mask_mode` into `masks`, dropping them if that isn't possible */
+fn migrate_masks(node: &mut Map<String, Value>, path: &str, changes: &mut Vec<Change>) {
+    let mut removed = |field| {
+        changes.push(Change::RemovedField {
+            path: path.to_string(),
+            field,
+        })
+    };
+
+    let sources = match node.remove("masked_by") {
+        Some(Value::Array(sources)) if !node.contains_key("masks") => Some(sources),
+        Some(_) => {
+            removed("masked_by");
+            None
+        }
+        None => None,
+    };
+    let mode = node.remove("mask_mode");
+    let (sources, mode) = match (sources, mode) {
+        (Some(sources), mode) => (sources, mode.unwrap_or_else(|| Value::from("Mask"))),
+        (None, Some(_)) => return removed("mask_mode"),
+        (None, None) => return,
+    };
+
+    let masks: Vec<Value> = sources
+        .into_iter()
+        .map(|source| json!({ "source": source, "mode": mode.clone() }))
+        .collect();
+    changes.push(Change::MaskBindings {
+        path: path.to_string(),
+        count: masks.len(),
+    });
+    node.insert("masks".into(), Value::Array(masks));
+}
+
+fn migrate_textures(node: &mut Map<String, Value>, path: &str, changes: &mut Vec<Change>) {
+    if !is_part(node) {
+        return;
+    }
+
+    if let Some(Value::Array(slots)) = node.get_mut("textures") {
+        if slots.len() < PART_TEXTURE_SLOTS {
+            changes.push(Change::TextureSlots {
+                path: path.to_string(),
+                from: slots.len(),
+            });
+            slots.resize(PART_TEXTURE_SLOTS, Value::from(NO_TEXTURE));
+        }
+    }
+}
+
+fn add_default(
+    object: &mut Map<String, Value>,
+    path: &str,
+    field: &'static str,
+    value: Value,
+    changes: &mut Vec<Change>,
+) {
+    if !object.contains_key(field) {
+        object.insert(field.into(), value);
+        changes.push(Change::AddedField {
+            path: path.to_string(),
+            field,
+        });
+    }
+}
+
+fn parse(json: &str) -> Result<Value, FormatError> {
+    serde_json::from_str(json).map_err(FormatError::Json)
+}
+
+/// Detect the schema revision of a puppet JSON document.
+///
+/// # Example
+///
+/// ~~~no_run
+/// let container = Container::open("./puppets/Ada.inx")?;
+///
+/// if format::detect_version(&container.json)? < SchemaVersion::Current {
+///     println!("Puppet needs migrating");
+/// }
+/// ~~~
+///
+pub fn detect_version(json: &str) -> Result<SchemaVersion, FormatError> {
+    Ok(detect(&mut parse(json)?))
+}
+
+/// Upgrade a puppet JSON document to the current schema.
+///
+/// Besides converting older structures, fields newer Inochi2D releases expect that are
+/// missing are added with their default values.
+///
+/// # Example
+///
+/// ~~~no_run
+/// let container = Container::open("./puppets/Ada.inx")?;
+/// let (json, report) = format::migrate_json(&container.json)?;
+///
+/// for change in report.changes.iter() {
+///     println!("{}", change);
+/// }
+/// ~~~
+///
+/// # Returns
+///
+/// - The upgraded document and a report of what changed, the document is returned
+///   unchanged if there was nothing to do.
+/// - Otherwise a `FormatError` if the document is not valid JSON.
+///
+pub fn migrate_json(json: &str) -> Result<(String, MigrationReport), FormatError> {
+    let mut doc = parse(json)?;
+    let from = detect(&mut doc);
+    let mut changes = Vec::new();
+
+    if let Some(nodes) = doc.get_mut("nodes") {
+        visit_nodes(nodes, "", &mut |node, path| {
+            migrate_masks(node, path, &mut changes);
+            migrate_textures(node, path, &mut changes);
+            add_default(node, path, "lockToRoot", Value::from(false), &mut changes);
+        });
+    }
+
+    if let Some(doc) = doc.as_object_mut() {
+        add_default(
+            doc,
+            "",
+            "physics",
+            json!({ "pixelsPerMeter": 1000.0, "gravity": 9.8 }),
+            &mut changes,
+        );
+        if let Some(Value::Object(meta)) = doc.get_mut("meta") {
+            add_default(
+                meta,
+                "meta",
+                "thumbnailId",
+                Value::from(NO_THUMBNAIL),
+                &mut changes,
+            );
+        }
+    }
+
+    let report = MigrationReport { from, changes };
+    if report.is_empty() {
+        return Ok((json.to_string(), report));
+    }
+
+    let json = serde_json::to_string(&doc).map_err(FormatError::Json)?;
+    Ok((json, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::model::{MaskMode, NodeKind, Puppet};
+
+    const LEGACY: &str = r#"{
+        "meta": { "version": "1.0-alpha" },
+        "nodes": {
+            "uuid": 1, "name": "Root", "type": "Node", "enabled": true, "zsort": 0,
+            "transform": { "trans": [0, 0, 0], "rot": [0, 0, 0], "scale": [1, 1] },
+            "children": [{
+                "uuid": 2, "name": "Head", "type": "Part", "enabled": true, "zsort": 0,
+                "transform": { "trans": [0, 0, 0], "rot": [0, 0, 0], "scale": [1, 1] },
+                "lockToRoot": false,
+                "mesh": { "verts": [], "uvs": [], "indices": [] },
+                "textures": [0], "blend_mode": "Normal", "tint": [1, 1, 1],
+                "mask_threshold": 0.5, "opacity": 1,
+                "masked_by": [3, 4], "mask_mode": "DodgeMask"
+            }]
+        },
+        "param": []
+    }"#;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            detect_version(LEGACY).unwrap(),
+            SchemaVersion::SharedMaskMode
+        );
+
+        let (json, _) = migrate_json(LEGACY).unwrap();
+        assert_eq!(detect_version(&json).unwrap(), SchemaVersion::Current);
+    }
+
+    #[test]
+    fn test_migrate() {
+        let (json, report) = migrate_json(LEGACY).unwrap();
+
+        assert_eq!(report.from, SchemaVersion::SharedMaskMode);
+        assert_eq!(
+            report.changes,
+            vec![
+                Change::AddedField {
+                    path: "Root".into(),
+                    field: "lockToRoot"
+                },
+                Change::MaskBindings {
+                    path: "Root/Head".into(),
+                    count: 2
+                },
+                Change::TextureSlots {
+                    path: "Root/Head".into(),
+                    from: 1
+                },
+                Change::AddedField {
+                    path: "".into(),
+                    field: "physics"
+                },
+                Change::AddedField {
+                    path: "meta".into(),
+                    field: "thumbnailId"
+                },
+            ]
+        );
+
+        let puppet = Puppet::from_json(&json).unwrap();
+        match &puppet.nodes.children[0].kind {
+            NodeKind::Part(part) => {
+                assert_eq!(part.textures, vec![0, NO_TEXTURE, NO_TEXTURE]);
+                assert_eq!(part.masks.len(), 2);
+                assert_eq!(part.masks[1].source, 4);
+                assert_eq!(part.masks[1].mode, MaskMode::DodgeMask);
+            }
+            _ => panic!("expected a part"),
+        }
+
+        /* Fields that can't be converted are still removed and reported */
+        let current = json.replacen(r#""masks":"#, r#""mask_mode":"Mask","masks":"#, 1);
+        assert_eq!(
+            detect_version(&current).unwrap(),
+            SchemaVersion::SharedMaskMode
+        );
+        let (fixed, report) = migrate_json(&current).unwrap();
+        assert_eq!(
+            report.changes,
+            vec![Change::RemovedField {
+                path: "Root/Head".into(),
+                field: "mask_mode"
+            }]
+        );
+        assert_eq!(detect_version(&fixed).unwrap(), SchemaVersion::Current);
+
+        /* Migrating again changes nothing */
+        let (again, report) = migrate_json(&json).unwrap();
+        assert!(report.is_empty());
+        assert_eq!(again, json);
+    }
+}
@@ -0,0 +1,609 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+/*!
+    Native support for the INP/INX puppet container, independent of the Inochi2D runtime.
+
+    A container is laid out as follows, with all integers being big-endian `u32`s:
+
+    - The magic `TRNSRTS\0`
+    - The length of the puppet JSON, followed by the JSON itself
+    - The `TEX_SECT` marker, the number of textures, and for each texture its length,
+      a one byte encoding tag and the encoded texture data
+    - Optionally the `EXT_SECT` marker, the number of vendor sections, and for each
+      section the length of its name, the name, the length of its payload and the payload
+*/
+
+mod limits;
+mod migrate;
+pub mod model;
+mod reader;
+mod thumbnail;
+mod validate;
+mod writer;
+
+pub use limits::{check_limits, Limit, LoadLimits};
+pub use migrate::{detect_version, migrate_json, Change, MigrationReport, SchemaVersion};
+pub use thumbnail::{render_thumbnail, Thumbnail, RENDERED_THUMBNAIL_SIZE};
+pub use validate::{has_errors, validate, Diagnostic, Issue, Location, MeshProblem, Severity};
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::Utf8Error;
+
+use crate::texture::TextureError;
+
+/// Magic bytes every INP/INX container starts with.
+pub const MAGIC: &[u8; 8] = b"TRNSRTS\0";
+/// Marker for the start of the texture section.
+pub const TEX_SECTION: &[u8; 8] = b"TEX_SECT";
+/// Marker for the start of the vendor extension section.
+pub const EXT_SECTION: &[u8; 8] = b"EXT_SECT";
+
+/// Errors produced while reading a puppet container.
+#[derive(Debug)]
+pub enum FormatError {
+    /// The data does not start with `TRNSRTS\0`.
+    InvalidMagic,
+    /// The data ended while `needed` more bytes were expected at `offset`.
+    UnexpectedEof { offset: usize, needed: usize },
+    /// The section marker `expected` was not found at `offset`.
+    MissingSection {
+        expected: &'static [u8; 8],
+        offset: usize,
+    },
+    /// The texture at `index` has an encoding tag this crate does not know.
+    UnknownTextureEncoding { index: usize, tag: u8 },
+    /// A string in the container was not valid UTF-8.
+    Utf8 { what: &'static str, err: Utf8Error },
+    /// The puppet JSON does not match the puppet model.
+    Json(serde_json::Error),
+    /// `what` is `len` bytes or entries long, more than the container can store.
+    TooLarge { what: &'static str, len: usize },
+    /// `limit` of the `LoadLimits` in use was exceeded, `value` is more than `max`.
+    LimitExceeded {
+        limit: Limit,
+        value: usize,
+        max: usize,
+    },
+    /// The texture at `index` could not be decoded.
+    Texture { index: usize, error: TextureError },
+    /// Re-encoding textures as `encoding` was asked for without allowing it, see
+    /// `texture::OptimizeOptions::allow_bc7`.
+    EncodingNotAllowed(TextureEncoding),
+    /// The puppet has no embedded thumbnail and nothing that can be rendered into one.
+    NoThumbnail,
+    /// Reading the container failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::InvalidMagic => write!(f, "Not an INP/INX puppet (bad magic)"),
+            FormatError::UnexpectedEof { offset, needed } => write!(
+                f,
+                "Unexpected end of data at offset {} ({} more bytes needed)",
+                offset, needed
+            ),
+            FormatError::MissingSection { expected, offset } => write!(
+                f,
+                "Expected section {} at offset {}",
+                String::from_utf8_lossy(&expected[..]),
+                offset
+            ),
+            FormatError::UnknownTextureEncoding { index, tag } => {
+                write!(f, "Texture {} has unknown encoding {}", index, tag)
+            }
+            FormatError::Utf8 { what, err } => write!(f, "Invalid UTF-8 in {}: {}", what, err),
+            FormatError::Json(err) => write!(f, "Invalid puppet JSON: {}", err),
+            FormatError::TooLarge { what, len } => {
+                write!(f, "The {} is too large to store ({})", what, len)
+            }
+            FormatError::LimitExceeded { limit, value, max } => {
+                write!(
+                    f,
+                    "Puppet {} of {} exceeds the limit of {}",
+                    limit, value, max
+                )
+            }
+            FormatError::Texture { index, error } => {
+                write!(f, "Unable to decode texture {}: {}", index, error)
+            }
+            FormatError::EncodingNotAllowed(encoding) => write!(
+                f,
+                "Re-encoding textures as {:?} was not allowed, the Inochi2D runtime may not load it",
+                encoding
+            ),
+            FormatError::NoThumbnail => write!(f, "Puppet has no thumbnail and nothing to render"),
+            FormatError::Io(err) => write!(f, "Unable to read puppet: {}", err),
+        }
+    }
+}
+
+impl Error for FormatError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FormatError::Utf8 { err, .. } => Some(err),
+            FormatError::Json(err) => Some(err),
+            FormatError::Texture { error, .. } => Some(error),
+            FormatError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FormatError {
+    fn from(err: io::Error) -> Self {
+        FormatError::Io(err)
+    }
+}
+
+/// How a texture in the texture section is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureEncoding {
+    Png,
+    Tga,
+    /// BC7 4x4 blocks in row-major order, behind a big-endian `u32` width and height.
+    ///
+    /// Inochi2D only defines the tag, not a layout for BC7 data. The size header is this
+    /// crate's own convention, so other tools and the Inochi2D runtime may not read textures
+    /// written by `texture::encode` or decode theirs.
+    ///
+    Bc7,
+}
+
+impl TextureEncoding {
+    /// Get the encoding tag stored in the container.
+    pub fn tag(self) -> u8 {
+        match self {
+            TextureEncoding::Png => 0,
+            TextureEncoding::Tga => 1,
+            TextureEncoding::Bc7 => 2,
+        }
+    }
+
+    /// Get the encoding for a tag stored in the container, if it is known.
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(TextureEncoding::Png),
+            1 => Some(TextureEncoding::Tga),
+            2 => Some(TextureEncoding::Bc7),
+            _ => None,
+        }
+    }
+}
+
+/// A single entry of the texture section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Texture {
+    pub encoding: TextureEncoding,
+    /// The encoded texture, empty for unused slots.
+    pub data: Vec<u8>,
+}
+
+/// A named payload from the vendor extension section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VendorData {
+    pub name: String,
+    pub payload: Vec<u8>,
+}
+
+/// The contents of an INP/INX container.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Container {
+    /// The puppet JSON document.
+    pub json: String,
+    /// The texture section, indexed by texture slot.
+    pub textures: Vec<Texture>,
+    /// The vendor extension section, in file order.
+    pub vendor: Vec<VendorData>,
+}
+
+impl Container {
+    /// Parse a container from an in-memory buffer.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let container = Container::parse(include_bytes!("./puppets/Ada.inx"))?;
+    ///
+    /// println!("{} textures", container.textures.len());
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// - If the data is a valid container its parsed contents.
+    /// - Otherwise a `FormatError` describing what is wrong with it.
+    ///
+    pub fn parse(data: &[u8]) -> Result<Self, FormatError> {
+        reader::parse(data)
+    }
+
+    /// Parse a container by reading `reader` until EOF.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self, FormatError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        Self::parse(&data)
+    }
+
+    /// Parse the container file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, FormatError> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    /// Parse the puppet JSON document into the typed model.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let container = Container::open("./puppets/Ada.inx")?;
+    /// let puppet = container.puppet()?;
+    ///
+    /// println!("{} parameters", puppet.param.len());
+    /// ~~~
+    ///
+    pub fn puppet(&self) -> Result<model::Puppet, FormatError> {
+        model::Puppet::from_json(&self.json).map_err(FormatError::Json)
+    }
+
+    /// Replace the puppet JSON document with the serialized `puppet`.
+    pub fn set_puppet(&mut self, puppet: &model::Puppet) -> Result<(), FormatError> {
+        self.json = puppet.to_json().map_err(FormatError::Json)?;
+
+        Ok(())
+    }
+
+    /// Check the puppet and its texture references for problems, see `format::validate`.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let diagnostics = Container::open("./puppets/Ada.inx")?.validate()?;
+    ///
+    /// if format::has_errors(&diagnostics) {
+    ///     return Err("puppet is broken".into());
+    /// }
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// - Every problem found in the puppet.
+    /// - A `FormatError` if the puppet JSON could not be parsed at all.
+    ///
+    pub fn validate(&self) -> Result<Vec<Diagnostic>, FormatError> {
+        Ok(validate(&self.puppet()?, Some(&self.textures)))
+    }
+
+    /// Detect the schema revision of the puppet JSON document, see `format::detect_version`.
+    pub fn schema_version(&self) -> Result<SchemaVersion, FormatError> {
+        detect_version(&self.json)
+    }
+
+    /// Upgrade the puppet JSON document to the current schema, see `format::migrate_json`.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let mut container = Container::open("./puppets/Ada.inx")?;
+    /// let report = container.migrate()?;
+    ///
+    /// if !report.is_empty() {
+    ///     println!("Upgraded from the {} schema", report.from);
+    ///     container.save("./puppets/Ada.inx")?;
+    /// }
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// - A report of what changed, the container is untouched if the report is empty.
+    /// - Otherwise a `FormatError` if the puppet JSON is not valid JSON.
+    ///
+    pub fn migrate(&mut self) -> Result<MigrationReport, FormatError> {
+        let (json, report) = migrate_json(&self.json)?;
+        self.json = json;
+
+        Ok(report)
+    }
+
+    /// Get the names of the vendor sections, in file order.
+    pub fn vendor_names(&self) -> impl Iterator<Item = &str> {
+        self.vendor.iter().map(|section| section.name.as_str())
+    }
+
+    /// Get the payload of the vendor section called `name`.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let container = Container::open("./puppets/Ada.inx")?;
+    ///
+    /// if let Some(config) = container.vendor_data("com.example.tracking") {
+    ///     println!("{} bytes of tracking config", config.len());
+    /// }
+    /// ~~~
+    ///
+    pub fn vendor_data(&self, name: &str) -> Option<&[u8]> {
+        self.vendor
+            .iter()
+            .find(|section| section.name == name)
+            .map(|section| section.payload.as_slice())
+    }
+
+    /// Set the payload of the vendor section called `name`, adding the section if needed.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let mut container = Container::open("./puppets/Ada.inx")?;
+    ///
+    /// container.set_vendor_data("com.example.hotkeys", br#"{"F1":"wave"}"#.to_vec());
+    /// container.save("./puppets/Ada.inx")?;
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// The previous payload of the section, if it existed.
+    ///
+    pub fn set_vendor_data(
+        &mut self,
+        name: impl Into<String>,
+        payload: impl Into<Vec<u8>>,
+    ) -> Option<Vec<u8>> {
+        set_vendor_data(&mut self.vendor, name.into(), payload.into())
+    }
+
+    /// Remove the vendor section called `name`, returning its payload.
+    pub fn remove_vendor_data(&mut self, name: &str) -> Option<Vec<u8>> {
+        remove_vendor_data(&mut self.vendor, name)
+    }
+
+    /// Serialize the container to `writer`.
+    ///
+    /// The vendor extension section is only written if there is vendor data.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let mut container = Container::open("./puppets/Ada.inx")?;
+    ///
+    /// container.json = container.json.replace("Ada", "Ada (edited)");
+    /// container.write(&mut File::create("./puppets/Ada-edited.inx")?)?;
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// - If serialization was successful `()`.
+    /// - Otherwise a `FormatError` describing the error.
+    ///
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), FormatError> {
+        writer::write(self, writer)
+    }
+
+    /// Serialize the container to an in-memory buffer.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, FormatError> {
+        let mut data = Vec::new();
+        self.write(&mut data)?;
+
+        Ok(data)
+    }
+
+    /// Serialize the container to the file at `path`, replacing it if it exists.
+    ///
+    /// The data is written to a temporary file next to `path` which is then renamed over it,
+    /// so if writing fails the original file is left as it was.
+    ///
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), FormatError> {
+        let path = path.as_ref();
+        let data = self.to_bytes()?;
+
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(format!(".{}.tmp", std::process::id()));
+        let temp = PathBuf::from(temp);
+
+        let res = fs::File::create(&temp)
+            .and_then(|mut file| {
+                file.write_all(&data)?;
+                if let Ok(meta) = fs::metadata(path) {
+                    file.set_permissions(meta.permissions())?;
+                }
+                file.sync_all()
+            })
+            .and_then(|()| fs::rename(&temp, path));
+        if res.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+
+        Ok(res?)
+    }
+}
+
+/* Replace or append a named vendor section, shared with `Inochi2DPuppet` */
+pub(crate) fn set_vendor_data(
+    vendor: &mut Vec<VendorData>,
+    name: String,
+    payload: Vec<u8>,
+) -> Option<Vec<u8>> {
+    match vendor.iter_mut().find(|section| section.name == name) {
+        Some(section) => Some(std::mem::replace(&mut section.payload, payload)),
+        None => {
+            vendor.push(VendorData { name, payload });
+            None
+        }
+    }
+}
+
+pub(crate) fn remove_vendor_data(vendor: &mut Vec<VendorData>, name: &str) -> Option<Vec<u8>> {
+    let index = vendor.iter().position(|section| section.name == name)?;
+
+    Some(vendor.remove(index).payload)
+}
+
+/* Only the `meta` block is deserialized, the rest of the document is skipped */
+#[derive(serde::Deserialize)]
+struct MetaOnly {
+    meta: model::PuppetMeta,
+}
+
+fn meta_from_json(json: &str) -> Result<model::PuppetMeta, FormatError> {
+    serde_json::from_str::<MetaOnly>(json)
+        .map(|doc| doc.meta)
+        .map_err(FormatError::Json)
+}
+
+/* Only the UUIDs and defaults of parameters are deserialized */
+#[cfg(feature = "runtime")]
+#[derive(serde::Deserialize)]
+struct ParamDefaults {
+    #[serde(default)]
+    param: Vec<ParamDefault>,
+}
+
+#[cfg(feature = "runtime")]
+#[derive(serde::Deserialize)]
+struct ParamDefault {
+    uuid: u32,
+    #[serde(default)]
+    defaults: [f32; 2],
+}
+
+/* Read the default value of each parameter, by UUID */
+#[cfg(feature = "runtime")]
+pub(crate) fn read_param_defaults_from_bytes(
+    data: &[u8],
+) -> Result<Vec<(u32, [f32; 2])>, FormatError> {
+    let doc: ParamDefaults =
+        serde_json::from_str(reader::json(data)?).map_err(FormatError::Json)?;
+
+    Ok(doc
+        .param
+        .into_iter()
+        .map(|param| (param.uuid, param.defaults))
+        .collect())
+}
+
+/// Read the metadata of the puppet file at `path`.
+///
+/// Only the start of the file up to the end of the puppet JSON is read, textures are
+/// skipped entirely.
+///
+/// # Example
+///
+/// ~~~no_run
+/// let meta = format::read_meta("./puppets/Ada.inx")?;
+///
+/// println!("{:?} by {:?}", meta.name, meta.artist);
+/// ~~~
+///
+/// # Returns
+///
+/// - If the file is a valid puppet its metadata.
+/// - Otherwise a `FormatError` describing the error.
+///
+pub fn read_meta(path: impl AsRef<Path>) -> Result<model::PuppetMeta, FormatError> {
+    let file = io::BufReader::new(fs::File::open(path)?);
+
+    meta_from_json(&reader::json_from_reader(file)?)
+}
+
+/// Read the metadata of a puppet stored in memory.
+pub fn read_meta_from_bytes(data: &[u8]) -> Result<model::PuppetMeta, FormatError> {
+    meta_from_json(reader::json(data)?)
+}
+
+/// Read the thumbnail of the puppet file at `path`, without loading the puppet.
+///
+/// The thumbnail texture named by the puppet's metadata is decoded, all other textures are
+/// skipped. If the puppet has no thumbnail the whole file is read and a rough one is rendered
+/// with `render_thumbnail` at `RENDERED_THUMBNAIL_SIZE`.
+///
+/// # Example
+///
+/// ~~~no_run
+/// let thumbnail = format::read_thumbnail("./puppets/Ada.inx")?;
+///
+/// println!("{}x{}", thumbnail.width, thumbnail.height);
+/// ~~~
+///
+/// # Returns
+///
+/// - The decoded thumbnail as RGBA8 pixels.
+/// - Otherwise a `FormatError` describing the error.
+///
+pub fn read_thumbnail(path: impl AsRef<Path>) -> Result<Thumbnail, FormatError> {
+    let path = path.as_ref();
+    let meta = read_meta(path)?;
+
+    let embedded = thumbnail::embedded(meta.thumbnail_id, |index| {
+        reader::texture_from_reader(io::BufReader::new(fs::File::open(path)?), index)
+    })?;
+
+    match embedded {
+        Some(thumbnail) => Ok(thumbnail),
+        None => render_thumbnail(&Container::open(path)?, RENDERED_THUMBNAIL_SIZE),
+    }
+}
+
+/// Read the thumbnail of a puppet stored in memory, see `read_thumbnail`.
+pub fn read_thumbnail_from_bytes(data: &[u8]) -> Result<Thumbnail, FormatError> {
+    let meta = read_meta_from_bytes(data)?;
+
+    match thumbnail::embedded(meta.thumbnail_id, |index| reader::texture(data, index))? {
+        Some(thumbnail) => Ok(thumbnail),
+        None => render_thumbnail(&Container::parse(data)?, RENDERED_THUMBNAIL_SIZE),
+    }
+}
+
+/// Read the vendor sections of the puppet file at `path`.
+///
+/// # Example
+///
+/// ~~~no_run
+/// for section in format::read_vendor_data("./puppets/Ada.inx")? {
+///     println!("{}: {} bytes", section.name, section.payload.len());
+/// }
+/// ~~~
+///
+pub fn read_vendor_data(path: impl AsRef<Path>) -> Result<Vec<VendorData>, FormatError> {
+    reader::vendor(&fs::read(path)?)
+}
+
+/// Read the vendor sections of a puppet stored in memory, without copying its textures.
+pub fn read_vendor_data_from_bytes(data: &[u8]) -> Result<Vec<VendorData>, FormatError> {
+    reader::vendor(data)
+}
+
+/// Replace the vendor sections of the puppet file at `path`, keeping everything else.
+///
+/// # Example
+///
+/// ~~~no_run
+/// let mut vendor = format::read_vendor_data("./puppets/Ada.inx")?;
+///
+/// vendor.retain(|section| section.name != "com.example.stale");
+/// format::write_vendor_data("./puppets/Ada.inx", vendor)?;
+/// ~~~
+///
+pub fn write_vendor_data(
+    path: impl AsRef<Path>,
+    vendor: Vec<VendorData>,
+) -> Result<(), FormatError> {
+    let path = path.as_ref();
+    let mut container = Container::open(path)?;
+    container.vendor = vendor;
+
+    container.save(path)
+}
+
+/// Check whether `data` starts with the INP/INX magic bytes.
+pub fn is_container(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
@@ -0,0 +1,877 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+/*!
+    Typed model of the puppet JSON document.
+
+    Every object keeps the fields it does not know about in `extra`, so a document
+    that is read and written back keeps all of its data.
+*/
+
+use std::collections::BTreeMap;
+
+use serde::de::{DeserializeOwned, Error as _};
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+
+/// Fields of an object that are not part of the typed model.
+pub type Extra = Map<String, Value>;
+
+/// `thumbnailId` value used when a puppet has no thumbnail.
+pub const NO_THUMBNAIL: u32 = u32::MAX;
+
+/// Texture slot value used when a slot of a part has no texture.
+pub const NO_TEXTURE: u32 = u32::MAX;
+
+/// The root of a puppet JSON document.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Puppet {
+    pub meta: PuppetMeta,
+    #[serde(default)]
+    pub physics: Physics,
+    pub nodes: Node,
+    #[serde(default)]
+    pub param: Vec<Param>,
+    #[serde(default)]
+    pub automation: Vec<Automation>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub animations: BTreeMap<String, Animation>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+impl Puppet {
+    /// Parse a puppet JSON document.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let container = Container::open("./puppets/Ada.inx")?;
+    /// let puppet = Puppet::from_json(&container.json)?;
+    ///
+    /// println!("{} parameters", puppet.param.len());
+    /// ~~~
+    ///
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    /// Serialize the puppet back to a JSON document.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    /// Find the parameter with the given UUID.
+    pub fn find_param(&self, uuid: u32) -> Option<&Param> {
+        self.param.iter().find(|param| param.uuid == uuid)
+    }
+}
+
+string_enum! {
+    /// Who may use a puppet.
+    pub enum AllowedUsers {
+        OnlyAuthor => "onlyAuthor",
+        OnlyLicensee => "onlyLicensee",
+        Everyone => "everyone",
+    }
+}
+
+string_enum! {
+    /// Whether a puppet may be redistributed.
+    pub enum AllowedRedistribution {
+        Prohibited => "prohibited",
+        ViaSource => "viaSource",
+        CopyleftLicense => "copyleftLicense",
+    }
+}
+
+string_enum! {
+    /// Whether a puppet may be modified.
+    pub enum AllowedModification {
+        Prohibited => "prohibited",
+        AllowPersonal => "allowPersonal",
+        AllowRedistribute => "allowRedistribute",
+    }
+}
+
+/// The usage rights block of the puppet metadata.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageRights {
+    pub allowed_users: AllowedUsers,
+    #[serde(default)]
+    pub allow_violence: bool,
+    #[serde(default)]
+    pub allow_sexual: bool,
+    #[serde(default)]
+    pub allow_commercial: bool,
+    pub allow_redistribution: AllowedRedistribution,
+    pub allow_modification: AllowedModification,
+    #[serde(default)]
+    pub require_attribution: bool,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// The `meta` block of a puppet.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PuppetMeta {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub rigger: Option<String>,
+    #[serde(default)]
+    pub artist: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rights: Option<UsageRights>,
+    #[serde(default)]
+    pub copyright: Option<String>,
+    #[serde(default, rename = "licenseURL")]
+    pub license_url: Option<String>,
+    #[serde(default)]
+    pub contact: Option<String>,
+    #[serde(default)]
+    pub reference: Option<String>,
+    #[serde(default = "no_thumbnail")]
+    pub thumbnail_id: u32,
+    #[serde(default)]
+    pub preserve_pixels: bool,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+fn no_thumbnail() -> u32 {
+    NO_THUMBNAIL
+}
+
+/// The `physics` block of a puppet.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Physics {
+    pub pixels_per_meter: f32,
+    pub gravity: f32,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+impl Default for Physics {
+    fn default() -> Self {
+        Physics {
+            pixels_per_meter: 1000.0,
+            gravity: 9.8,
+            extra: Extra::new(),
+        }
+    }
+}
+
+/// A node's transform relative to its parent.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Transform {
+    pub trans: [f32; 3],
+    pub rot: [f32; 3],
+    pub scale: [f32; 2],
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            trans: [0.0; 3],
+            rot: [0.0; 3],
+            scale: [1.0; 2],
+            extra: Extra::new(),
+        }
+    }
+}
+
+/// The mesh of a drawable node, vertices and UVs are stored as flat `x, y` pairs.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Mesh {
+    #[serde(default)]
+    pub verts: Vec<f32>,
+    #[serde(default)]
+    pub uvs: Vec<f32>,
+    #[serde(default)]
+    pub indices: Vec<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<[f32; 2]>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+impl Mesh {
+    /// Get the number of vertices in the mesh.
+    pub fn vertex_count(&self) -> usize {
+        self.verts.len() / 2
+    }
+}
+
+string_enum! {
+    /// How a part or composite is blended with what is below it.
+    pub enum BlendMode {
+        Normal => "Normal",
+        Multiply => "Multiply",
+        ColorDodge => "ColorDodge",
+        LinearDodge => "LinearDodge",
+        Screen => "Screen",
+        ClipToLower => "ClipToLower",
+        SliceFromLower => "SliceFromLower",
+    }
+}
+
+string_enum! {
+    /// How a mask source is applied.
+    pub enum MaskMode {
+        Mask => "Mask",
+        DodgeMask => "DodgeMask",
+    }
+}
+
+/// A reference to a node used as a mask.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaskBinding {
+    pub source: u32,
+    pub mode: MaskMode,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+fn blend_normal() -> BlendMode {
+    BlendMode::Normal
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+fn white() -> [f32; 3] {
+    [1.0; 3]
+}
+
+fn mask_threshold() -> f32 {
+    0.5
+}
+
+/// Data of a `Part` node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Part {
+    pub mesh: Mesh,
+    #[serde(default)]
+    pub textures: Vec<u32>,
+    #[serde(default = "blend_normal", rename = "blend_mode")]
+    pub blend_mode: BlendMode,
+    #[serde(default = "white")]
+    pub tint: [f32; 3],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screen_tint: Option<[f32; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emission_strength: Option<f32>,
+    #[serde(default = "mask_threshold", rename = "mask_threshold")]
+    pub mask_threshold: f32,
+    #[serde(default = "one")]
+    pub opacity: f32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub masks: Vec<MaskBinding>,
+}
+
+/// Data of a `Mask` node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mask {
+    pub mesh: Mesh,
+}
+
+/// Data of a `Composite` node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Composite {
+    #[serde(default = "blend_normal", rename = "blend_mode")]
+    pub blend_mode: BlendMode,
+    #[serde(default = "white")]
+    pub tint: [f32; 3],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screen_tint: Option<[f32; 3]>,
+    #[serde(default = "mask_threshold", rename = "mask_threshold")]
+    pub mask_threshold: f32,
+    #[serde(default = "one")]
+    pub opacity: f32,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        rename = "propagate_meshgroup"
+    )]
+    pub propagate_meshgroup: Option<bool>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub masks: Vec<MaskBinding>,
+}
+
+string_enum! {
+    /// The physics model of a `SimplePhysics` node.
+    pub enum PhysicsModel {
+        Pendulum => "Pendulum",
+        SpringPendulum => "SpringPendulum",
+    }
+}
+
+string_enum! {
+    /// How a `SimplePhysics` node maps its output onto its parameter.
+    pub enum PhysicsMapMode {
+        AngleLength => "AngleLength",
+        XY => "XY",
+    }
+}
+
+/// Data of a `SimplePhysics` node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimplePhysics {
+    /// UUID of the driven parameter.
+    pub param: u32,
+    pub model_type: PhysicsModel,
+    pub map_mode: PhysicsMapMode,
+    pub gravity: f32,
+    pub length: f32,
+    pub frequency: f32,
+    pub angle_damping: f32,
+    pub length_damping: f32,
+    pub output_scale: [f32; 2],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_only: Option<bool>,
+}
+
+/// Data of a `MeshGroup` node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MeshGroup {
+    pub mesh: Mesh,
+    #[serde(default)]
+    pub dynamic_deformation: bool,
+    #[serde(default)]
+    pub translate_children: bool,
+}
+
+/// The kind of a node, along with the data specific to that kind.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeKind {
+    Node,
+    Part(Part),
+    Mask(Mask),
+    Composite(Composite),
+    SimplePhysics(SimplePhysics),
+    MeshGroup(MeshGroup),
+    /// A node type not known to this crate, its data is kept in `Node::extra`.
+    Other(String),
+}
+
+impl NodeKind {
+    /// Get the `type` string stored in the puppet JSON.
+    pub fn type_name(&self) -> &str {
+        match self {
+            NodeKind::Node => "Node",
+            NodeKind::Part(_) => "Part",
+            NodeKind::Mask(_) => "Mask",
+            NodeKind::Composite(_) => "Composite",
+            NodeKind::SimplePhysics(_) => "SimplePhysics",
+            NodeKind::MeshGroup(_) => "MeshGroup",
+            NodeKind::Other(name) => name,
+        }
+    }
+
+    /// Get the mesh of drawable nodes.
+    pub fn mesh(&self) -> Option<&Mesh> {
+        match self {
+            NodeKind::Part(part) => Some(&part.mesh),
+            NodeKind::Mask(mask) => Some(&mask.mesh),
+            NodeKind::MeshGroup(group) => Some(&group.mesh),
+            _ => None,
+        }
+    }
+
+    /// Get the masks applied to parts and composites.
+    pub fn masks(&self) -> &[MaskBinding] {
+        match self {
+            NodeKind::Part(part) => &part.masks,
+            NodeKind::Composite(composite) => &composite.masks,
+            _ => &[],
+        }
+    }
+}
+
+/// A node in the puppet's node tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub uuid: u32,
+    pub name: String,
+    pub enabled: bool,
+    pub zsort: f32,
+    pub transform: Transform,
+    pub lock_to_root: bool,
+    pub kind: NodeKind,
+    pub children: Vec<Node>,
+    pub extra: Extra,
+}
+
+impl Node {
+    /// Iterate over this node and all of its descendants, depth first.
+    pub fn iter(&self) -> NodeIter<'_> {
+        NodeIter { stack: vec![self] }
+    }
+
+    /// Find the node with the given UUID in this subtree.
+    pub fn find(&self, uuid: u32) -> Option<&Node> {
+        self.iter().find(|node| node.uuid == uuid)
+    }
+
+    /// Get the texture slots the node refers to, without `NO_TEXTURE` entries.
+    ///
+    /// Besides the textures of parts this includes a `textures` array on any other kind of
+    /// node, such as node types this crate does not model.
+    ///
+    pub fn texture_slots(&self) -> Vec<u32> {
+        let part = match &self.kind {
+            NodeKind::Part(part) => &part.textures[..],
+            _ => &[],
+        };
+        let extra = match self.extra.get("textures") {
+            Some(Value::Array(slots)) => &slots[..],
+            _ => &[],
+        };
+
+        part.iter()
+            .copied()
+            .chain(
+                extra
+                    .iter()
+                    .filter_map(|slot| slot.as_u64().and_then(|slot| u32::try_from(slot).ok())),
+            )
+            .filter(|&slot| slot != NO_TEXTURE)
+            .collect()
+    }
+
+    /// Call `f` on this node and all of its descendants, depth first.
+    pub fn visit_mut(&mut self, f: &mut impl FnMut(&mut Node)) {
+        f(self);
+        for child in self.children.iter_mut() {
+            child.visit_mut(f);
+        }
+    }
+}
+
+/// Depth first iterator over a node tree.
+pub struct NodeIter<'a> {
+    stack: Vec<&'a Node>,
+}
+
+impl<'a> Iterator for NodeIter<'a> {
+    type Item = &'a Node;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.stack.extend(node.children.iter().rev());
+        Some(node)
+    }
+}
+
+/* The fields common to all nodes, the rest is interpreted based on `type` */
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawNode {
+    uuid: u32,
+    #[serde(default)]
+    name: String,
+    #[serde(rename = "type", default = "node_type")]
+    type_name: String,
+    #[serde(default = "enabled")]
+    enabled: bool,
+    #[serde(default)]
+    zsort: f32,
+    #[serde(default)]
+    transform: Transform,
+    #[serde(default)]
+    lock_to_root: bool,
+    #[serde(flatten)]
+    rest: Extra,
+    #[serde(default)]
+    children: Vec<Node>,
+}
+
+fn node_type() -> String {
+    "Node".into()
+}
+
+fn enabled() -> bool {
+    true
+}
+
+/* Splits an object into the known fields of `T` and everything else */
+#[derive(Deserialize)]
+struct WithExtra<T> {
+    #[serde(flatten)]
+    data: T,
+    #[serde(flatten)]
+    extra: Extra,
+}
+
+fn split<T: DeserializeOwned>(rest: Extra) -> serde_json::Result<(T, Extra)> {
+    let split: WithExtra<T> = serde_json::from_value(Value::Object(rest))?;
+    Ok((split.data, split.extra))
+}
+
+fn merge<T: Serialize>(data: &T, extra: &Extra) -> serde_json::Result<Extra> {
+    match serde_json::to_value(data)? {
+        Value::Object(mut fields) => {
+            fields.extend(extra.iter().map(|(k, v)| (k.clone(), v.clone())));
+            Ok(fields)
+        }
+        _ => Err(serde::ser::Error::custom("node data is not an object")),
+    }
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = RawNode::deserialize(deserializer)?;
+
+        let (kind, extra) = match raw.type_name.as_str() {
+            "Node" => Ok((NodeKind::Node, raw.rest)),
+            "Part" => split(raw.rest).map(|(d, e)| (NodeKind::Part(d), e)),
+            "Mask" => split(raw.rest).map(|(d, e)| (NodeKind::Mask(d), e)),
+            "Composite" => split(raw.rest).map(|(d, e)| (NodeKind::Composite(d), e)),
+            "SimplePhysics" => split(raw.rest).map(|(d, e)| (NodeKind::SimplePhysics(d), e)),
+            "MeshGroup" => split(raw.rest).map(|(d, e)| (NodeKind::MeshGroup(d), e)),
+            _ => Ok((NodeKind::Other(raw.type_name.clone()), raw.rest)),
+        }
+        .map_err(|err| {
+            D::Error::custom(format!(
+                "invalid {} node {}: {}",
+                raw.type_name, raw.uuid, err
+            ))
+        })?;
+
+        Ok(Node {
+            uuid: raw.uuid,
+            name: raw.name,
+            enabled: raw.enabled,
+            zsort: raw.zsort,
+            transform: raw.transform,
+            lock_to_root: raw.lock_to_root,
+            kind,
+            children: raw.children,
+            extra,
+        })
+    }
+}
+
+/* Borrowed version of `RawNode`, so serializing doesn't copy the whole tree */
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RawNodeRef<'a> {
+    uuid: u32,
+    name: &'a str,
+    #[serde(rename = "type")]
+    type_name: &'a str,
+    enabled: bool,
+    zsort: f32,
+    transform: &'a Transform,
+    lock_to_root: bool,
+    #[serde(flatten)]
+    rest: &'a Extra,
+    children: &'a [Node],
+}
+
+impl Serialize for Node {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let rest = match &self.kind {
+            NodeKind::Node | NodeKind::Other(_) => Ok(self.extra.clone()),
+            NodeKind::Part(data) => merge(data, &self.extra),
+            NodeKind::Mask(data) => merge(data, &self.extra),
+            NodeKind::Composite(data) => merge(data, &self.extra),
+            NodeKind::SimplePhysics(data) => merge(data, &self.extra),
+            NodeKind::MeshGroup(data) => merge(data, &self.extra),
+        }
+        .map_err(S::Error::custom)?;
+
+        RawNodeRef {
+            uuid: self.uuid,
+            name: &self.name,
+            type_name: self.kind.type_name(),
+            enabled: self.enabled,
+            zsort: self.zsort,
+            transform: &self.transform,
+            lock_to_root: self.lock_to_root,
+            rest: &rest,
+            children: &self.children,
+        }
+        .serialize(serializer)
+    }
+}
+
+string_enum! {
+    /// How values are interpolated between keypoints.
+    pub enum InterpolateMode {
+        Nearest => "Nearest",
+        Linear => "Linear",
+        Cubic => "Cubic",
+    }
+}
+
+/// A single value of a parameter binding.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BindingValue {
+    /// A value for a transform or other scalar binding.
+    Scalar(f32),
+    /// Per-vertex offsets for a deform binding.
+    Deform(Vec<[f32; 2]>),
+}
+
+/// A binding between a parameter and a property of a node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Binding {
+    /// UUID of the bound node.
+    pub node: u32,
+    /// The bound property, such as `transform.t.x` or `deform`.
+    pub param_name: String,
+    /// Values for each keypoint, indexed by X then Y axis point.
+    pub values: Vec<Vec<BindingValue>>,
+    /// Whether each keypoint has a value set.
+    #[serde(rename = "isSet")]
+    pub is_set: Vec<Vec<bool>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interpolate_mode: Option<InterpolateMode>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// A puppet parameter.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Param {
+    pub uuid: u32,
+    pub name: String,
+    #[serde(default)]
+    pub is_vec2: bool,
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+    #[serde(default)]
+    pub defaults: [f32; 2],
+    /// Keypoint positions for the X and Y axis, normalized to 0..1.
+    pub axis_points: [Vec<f32>; 2],
+    #[serde(default)]
+    pub bindings: Vec<Binding>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// Maps an automation onto a parameter axis.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AutomationBinding {
+    /// UUID of the driven parameter.
+    pub param: u32,
+    pub axis: u32,
+    #[serde(default)]
+    pub range: [f32; 2],
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// A procedural parameter automation, such as a sine wave or physics.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Automation {
+    #[serde(rename = "type")]
+    pub automation_type: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub bindings: Vec<AutomationBinding>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// A keyframe of an animation lane.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub frame: i32,
+    pub value: f32,
+    #[serde(default)]
+    pub tension: f32,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// An animation lane driving one axis of a parameter.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnimationLane {
+    pub interpolation: InterpolateMode,
+    /// UUID of the animated parameter.
+    pub uuid: u32,
+    /// The animated parameter axis.
+    pub target: u32,
+    #[serde(default)]
+    pub keyframes: Vec<Keyframe>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// A keyframed animation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Animation {
+    pub timestep: f32,
+    #[serde(default)]
+    pub additive: bool,
+    #[serde(default = "one")]
+    pub animation_weight: f32,
+    pub length: i32,
+    #[serde(default)]
+    pub lead_in: i32,
+    #[serde(default)]
+    pub lead_out: i32,
+    #[serde(default)]
+    pub lanes: Vec<AnimationLane>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"{
+        "meta": {
+            "name": "Ada", "version": "1.0-alpha", "rigger": null, "artist": "Someone",
+            "rights": {
+                "allowedUsers": "everyone", "allowViolence": false, "allowSexual": false,
+                "allowCommercial": true, "allowRedistribution": "viaSource",
+                "allowModification": "allowPersonal", "requireAttribution": true
+            },
+            "copyright": null, "licenseURL": null, "contact": null, "reference": null,
+            "thumbnailId": 4294967295, "preservePixels": false, "vendorField": 3
+        },
+        "physics": { "pixelsPerMeter": 1000, "gravity": 9.8 },
+        "nodes": {
+            "uuid": 1, "name": "Root", "type": "Node", "enabled": true, "zsort": 0,
+            "transform": { "trans": [0, 0, 0], "rot": [0, 0, 0], "scale": [1, 1] },
+            "lockToRoot": false,
+            "children": [{
+                "uuid": 2, "name": "Head", "type": "Part", "enabled": true, "zsort": 0,
+                "transform": { "trans": [0, -10, 0], "rot": [0, 0, 0], "scale": [1, 1] },
+                "lockToRoot": false,
+                "mesh": { "verts": [0, 0, 1, 0, 0, 1], "uvs": [0, 0, 1, 0, 0, 1], "indices": [0, 1, 2] },
+                "textures": [0, 4294967295, 4294967295], "blend_mode": "Normal",
+                "tint": [1, 1, 1], "mask_threshold": 0.5, "opacity": 1,
+                "masks": [{ "source": 3, "mode": "Mask" }],
+                "psdLayerPath": "/Head",
+                "children": []
+            }, {
+                "uuid": 3, "name": "Glow", "type": "SpecialNode", "magic": [1, 2],
+                "enabled": true, "zsort": 0,
+                "transform": { "trans": [0, 0, 0], "rot": [0, 0, 0], "scale": [1, 1] },
+                "lockToRoot": false, "children": []
+            }]
+        },
+        "param": [{
+            "uuid": 10, "name": "Head:: Yaw-Pitch", "is_vec2": true,
+            "min": [-1, -1], "max": [1, 1], "defaults": [0, 0],
+            "axis_points": [[0, 0.5, 1], [0, 1]],
+            "bindings": [{
+                "node": 2, "param_name": "transform.t.x",
+                "values": [[0, 1], [0, 1], [2, 3]],
+                "isSet": [[true, true], [true, true], [true, true]],
+                "interpolate_mode": "Linear"
+            }, {
+                "node": 2, "param_name": "deform",
+                "values": [[[[0, 0], [1, 1], [0, 0]]]],
+                "isSet": [[true]]
+            }]
+        }],
+        "automation": [{ "type": "sine", "name": "Breathe", "bindings": [], "speed": 1 }],
+        "animations": {
+            "Wave": {
+                "timestep": 0.0166, "additive": false, "animationWeight": 1, "length": 60,
+                "leadIn": 0, "leadOut": 0,
+                "lanes": [{
+                    "interpolation": "Cubic", "uuid": 10, "target": 0,
+                    "keyframes": [{ "frame": 0, "value": 0, "tension": 0.5 }]
+                }]
+            }
+        },
+        "futureField": { "answer": 42 }
+    }"#;
+
+    #[test]
+    fn test_parse() {
+        let puppet = Puppet::from_json(SAMPLE).unwrap();
+
+        assert_eq!(puppet.meta.name.as_deref(), Some("Ada"));
+        assert_eq!(
+            puppet.meta.rights.as_ref().unwrap().allowed_users,
+            AllowedUsers::Everyone
+        );
+        assert_eq!(puppet.meta.thumbnail_id, NO_THUMBNAIL);
+        assert_eq!(puppet.meta.extra["vendorField"], 3);
+        assert_eq!(puppet.extra["futureField"]["answer"], 42);
+
+        let head = puppet.nodes.find(2).unwrap();
+        match &head.kind {
+            NodeKind::Part(part) => {
+                assert_eq!(part.mesh.vertex_count(), 3);
+                assert_eq!(part.masks[0].source, 3);
+                assert_eq!(part.blend_mode, BlendMode::Normal);
+            }
+            kind => panic!("expected a Part, got {:?}", kind),
+        }
+        assert_eq!(head.extra["psdLayerPath"], "/Head");
+
+        let glow = puppet.nodes.find(3).unwrap();
+        assert_eq!(glow.kind, NodeKind::Other("SpecialNode".into()));
+        assert_eq!(glow.extra["magic"], serde_json::json!([1, 2]));
+
+        let param = puppet.find_param(10).unwrap();
+        assert_eq!(param.bindings[0].values[2][1], BindingValue::Scalar(3.0));
+        assert_eq!(
+            param.bindings[1].values[0][0],
+            BindingValue::Deform(vec![[0.0, 0.0], [1.0, 1.0], [0.0, 0.0]])
+        );
+
+        assert_eq!(puppet.automation[0].extra["speed"], 1);
+        assert_eq!(
+            puppet.animations["Wave"].lanes[0].interpolation,
+            InterpolateMode::Cubic
+        );
+        assert_eq!(
+            puppet.nodes.iter().map(|n| n.uuid).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+    }
+
+    /* Integers are written back as floats, so compare all numbers as f64 */
+    fn normalize(value: Value) -> Value {
+        match value {
+            Value::Number(n) => serde_json::json!(n.as_f64().unwrap()),
+            Value::Array(items) => Value::Array(items.into_iter().map(normalize).collect()),
+            Value::Object(fields) => {
+                Value::Object(fields.into_iter().map(|(k, v)| (k, normalize(v))).collect())
+            }
+            value => value,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let original: Value = serde_json::from_str(SAMPLE).unwrap();
+        let puppet = Puppet::from_json(SAMPLE).unwrap();
+        let written: Value = serde_json::from_str(&puppet.to_json().unwrap()).unwrap();
+
+        assert_eq!(normalize(written), normalize(original));
+        assert_eq!(
+            Puppet::from_json(&puppet.to_json().unwrap()).unwrap(),
+            puppet
+        );
+    }
+}
@@ -0,0 +1,438 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::str;
+
+use super::{
+    Container, FormatError, Texture, TextureEncoding, VendorData, EXT_SECTION, MAGIC, TEX_SECTION,
+};
+
+/* A bounds checked cursor over the container data */
+struct Cursor<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn remaining(&self) -> usize {
+        self.data.len() - self.offset
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], FormatError> {
+        if self.remaining() < len {
+            return Err(FormatError::UnexpectedEof {
+                offset: self.offset,
+                needed: len - self.remaining(),
+            });
+        }
+
+        let slice = &self.data[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, FormatError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /* A u32 length prefix followed by that many bytes */
+    fn blob(&mut self) -> Result<&'a [u8], FormatError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn section(&mut self, marker: &'static [u8; 8]) -> Result<(), FormatError> {
+        let offset = self.offset;
+        match self.take(8) {
+            Ok(found) if found == marker => Ok(()),
+            _ => Err(FormatError::MissingSection {
+                expected: marker,
+                offset,
+            }),
+        }
+    }
+
+    fn peek_section(&self, marker: &[u8; 8]) -> bool {
+        self.data[self.offset..].starts_with(marker)
+    }
+}
+
+fn utf8<'a>(bytes: &'a [u8], what: &'static str) -> Result<&'a str, FormatError> {
+    str::from_utf8(bytes).map_err(|err| FormatError::Utf8 { what, err })
+}
+
+fn header(data: &[u8]) -> Result<(Cursor<'_>, &str), FormatError> {
+    if !data.starts_with(MAGIC) {
+        return Err(FormatError::InvalidMagic);
+    }
+
+    let mut cursor = Cursor {
+        data,
+        offset: MAGIC.len(),
+    };
+
+    let json = utf8(cursor.blob()?, "puppet JSON")?;
+    Ok((cursor, json))
+}
+
+/* Read just the puppet JSON, without looking at the texture or vendor sections */
+pub(super) fn json(data: &[u8]) -> Result<&str, FormatError> {
+    header(data).map(|(_, json)| json)
+}
+
+/* Read just the puppet JSON from the start of a stream */
+pub(super) fn json_from_reader<R: Read>(mut reader: R) -> Result<String, FormatError> {
+    let mut magic = [0u8; 8];
+    let mut len = [0u8; 4];
+    read_header_bytes(&mut reader, &mut magic, 0)?;
+    if &magic != MAGIC {
+        return Err(FormatError::InvalidMagic);
+    }
+    read_header_bytes(&mut reader, &mut len, magic.len())?;
+
+    let len = u32::from_be_bytes(len) as usize;
+    let mut json = Vec::new();
+    let read = reader.take(len as u64).read_to_end(&mut json)?;
+    if read < len {
+        return Err(FormatError::UnexpectedEof {
+            offset: magic.len() + 4 + read,
+            needed: len - read,
+        });
+    }
+
+    String::from_utf8(json).map_err(|err| FormatError::Utf8 {
+        what: "puppet JSON",
+        err: err.utf8_error(),
+    })
+}
+
+fn read_header_bytes<R: Read>(
+    reader: &mut R,
+    buf: &mut [u8],
+    offset: usize,
+) -> Result<(), FormatError> {
+    reader.read_exact(buf).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => FormatError::UnexpectedEof {
+            offset,
+            needed: buf.len(),
+        },
+        _ => FormatError::Io(err),
+    })
+}
+
+/* Read the header of a texture section entry, returning its length and encoding */
+fn texture_header(
+    cursor: &mut Cursor<'_>,
+    index: usize,
+) -> Result<(usize, TextureEncoding), FormatError> {
+    let len = cursor.u32()? as usize;
+    let tag = cursor.u8()?;
+    let encoding =
+        TextureEncoding::from_tag(tag).ok_or(FormatError::UnknownTextureEncoding { index, tag })?;
+
+    Ok((len, encoding))
+}
+
+/* Read the texture in slot `index` without copying the other textures */
+pub(super) fn texture(data: &[u8], index: usize) -> Result<Option<Texture>, FormatError> {
+    let (mut cursor, _) = header(data)?;
+
+    cursor.section(TEX_SECTION)?;
+    let texture_count = cursor.u32()? as usize;
+    if index >= texture_count {
+        return Ok(None);
+    }
+
+    for i in 0..=index {
+        let (len, encoding) = texture_header(&mut cursor, i)?;
+        let data = cursor.take(len)?;
+        if i == index {
+            return Ok(Some(Texture {
+                encoding,
+                data: data.to_vec(),
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+/* Read the texture in slot `index` from a stream, seeking past the ones before it */
+pub(super) fn texture_from_reader<R: Read + Seek>(
+    mut reader: R,
+    index: usize,
+) -> Result<Option<Texture>, FormatError> {
+    let json_len = json_from_reader(&mut reader)?.len();
+    let mut offset = MAGIC.len() + 4 + json_len;
+
+    let mut marker = [0u8; 8];
+    read_header_bytes(&mut reader, &mut marker, offset)?;
+    if &marker != TEX_SECTION {
+        return Err(FormatError::MissingSection {
+            expected: TEX_SECTION,
+            offset,
+        });
+    }
+    offset += marker.len();
+
+    let mut entry = [0u8; 5];
+    read_header_bytes(&mut reader, &mut entry[..4], offset)?;
+    offset += 4;
+    let texture_count = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]) as usize;
+    if index >= texture_count {
+        return Ok(None);
+    }
+
+    for i in 0..=index {
+        read_header_bytes(&mut reader, &mut entry, offset)?;
+        offset += entry.len();
+        let (len, encoding) = texture_header(
+            &mut Cursor {
+                data: &entry,
+                offset: 0,
+            },
+            i,
+        )?;
+
+        if i < index {
+            reader.seek(SeekFrom::Current(len as i64))?;
+            offset += len;
+            continue;
+        }
+
+        let mut data = Vec::new();
+        let read = (&mut reader).take(len as u64).read_to_end(&mut data)?;
+        if read < len {
+            return Err(FormatError::UnexpectedEof {
+                offset: offset + read,
+                needed: len - read,
+            });
+        }
+
+        return Ok(Some(Texture { encoding, data }));
+    }
+
+    Ok(None)
+}
+
+/* Read the vendor section at the cursor, if there is one */
+fn vendor_section(cursor: &mut Cursor<'_>) -> Result<Vec<VendorData>, FormatError> {
+    let mut vendor = Vec::new();
+    if cursor.peek_section(EXT_SECTION) {
+        cursor.section(EXT_SECTION)?;
+        let section_count = cursor.u32()? as usize;
+        for _ in 0..section_count {
+            let name = utf8(cursor.blob()?, "vendor section name")?.to_string();
+            let payload = cursor.blob()?.to_vec();
+
+            vendor.push(VendorData { name, payload });
+        }
+    }
+
+    Ok(vendor)
+}
+
+/* Read just the vendor section, skipping over the textures without copying them */
+pub(super) fn vendor(data: &[u8]) -> Result<Vec<VendorData>, FormatError> {
+    let (mut cursor, _) = header(data)?;
+
+    cursor.section(TEX_SECTION)?;
+    let texture_count = cursor.u32()? as usize;
+    for index in 0..texture_count {
+        let (len, _) = texture_header(&mut cursor, index)?;
+        cursor.take(len)?;
+    }
+
+    vendor_section(&mut cursor)
+}
+
+/* Read the number of textures the texture section claims to hold */
+pub(super) fn texture_count(data: &[u8]) -> Result<usize, FormatError> {
+    let (mut cursor, _) = header(data)?;
+
+    cursor.section(TEX_SECTION)?;
+    Ok(cursor.u32()? as usize)
+}
+
+/* Borrow every texture without copying it */
+pub(super) fn texture_slices(data: &[u8]) -> Result<Vec<(TextureEncoding, &[u8])>, FormatError> {
+    let (mut cursor, _) = header(data)?;
+
+    cursor.section(TEX_SECTION)?;
+    let texture_count = cursor.u32()? as usize;
+    let mut textures = Vec::with_capacity(texture_count.min(cursor.remaining() / 5));
+    for index in 0..texture_count {
+        let (len, encoding) = texture_header(&mut cursor, index)?;
+        textures.push((encoding, cursor.take(len)?));
+    }
+
+    Ok(textures)
+}
+
+pub(super) fn parse(data: &[u8]) -> Result<Container, FormatError> {
+    let (mut cursor, json) = header(data)?;
+    let json = json.to_string();
+
+    cursor.section(TEX_SECTION)?;
+    let texture_count = cursor.u32()? as usize;
+    /* Don't trust the count for preallocation, each texture needs at least 5 bytes */
+    let mut textures = Vec::with_capacity(texture_count.min(cursor.remaining() / 5));
+    for index in 0..texture_count {
+        let (len, encoding) = texture_header(&mut cursor, index)?;
+
+        textures.push(Texture {
+            encoding,
+            data: cursor.take(len)?.to_vec(),
+        });
+    }
+
+    let vendor = vendor_section(&mut cursor)?;
+
+    Ok(Container {
+        json,
+        textures,
+        vendor,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blob(out: &mut Vec<u8>, data: &[u8]) {
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.extend_from_slice(data);
+    }
+
+    fn sample() -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        blob(&mut data, br#"{"meta":{"name":"Ada"}}"#);
+        data.extend_from_slice(TEX_SECTION);
+        data.extend_from_slice(&2u32.to_be_bytes());
+        data.extend_from_slice(&3u32.to_be_bytes());
+        data.push(0);
+        data.extend_from_slice(b"png");
+        data.extend_from_slice(&0u32.to_be_bytes());
+        data.push(2);
+        data.extend_from_slice(EXT_SECTION);
+        data.extend_from_slice(&1u32.to_be_bytes());
+        blob(&mut data, b"com.example.tracking");
+        blob(&mut data, b"{}");
+        data
+    }
+
+    #[test]
+    fn test_parse() {
+        let container = parse(&sample()).unwrap();
+
+        assert_eq!(container.json, r#"{"meta":{"name":"Ada"}}"#);
+        assert_eq!(
+            container.textures,
+            vec![
+                Texture {
+                    encoding: TextureEncoding::Png,
+                    data: b"png".to_vec()
+                },
+                Texture {
+                    encoding: TextureEncoding::Bc7,
+                    data: Vec::new()
+                },
+            ]
+        );
+        assert_eq!(
+            container.vendor,
+            vec![VendorData {
+                name: "com.example.tracking".into(),
+                payload: b"{}".to_vec()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_without_vendor_section() {
+        let mut data = MAGIC.to_vec();
+        blob(&mut data, b"{}");
+        data.extend_from_slice(TEX_SECTION);
+        data.extend_from_slice(&0u32.to_be_bytes());
+
+        let container = parse(&data).unwrap();
+        assert!(container.textures.is_empty());
+        assert!(container.vendor.is_empty());
+    }
+
+    #[test]
+    fn test_json_only() {
+        let data = sample();
+
+        assert_eq!(json(&data).unwrap(), r#"{"meta":{"name":"Ada"}}"#);
+        assert_eq!(
+            json_from_reader(&data[..]).unwrap(),
+            r#"{"meta":{"name":"Ada"}}"#
+        );
+        /* The texture section isn't needed to get to the JSON */
+        assert!(json_from_reader(&data[..8 + 4 + 23]).is_ok());
+        assert!(matches!(
+            json_from_reader(&data[..8 + 4 + 5]),
+            Err(FormatError::UnexpectedEof { .. })
+        ));
+    }
+
+    #[test]
+    fn test_single_texture() {
+        let data = sample();
+        let expected = Texture {
+            encoding: TextureEncoding::Bc7,
+            data: Vec::new(),
+        };
+
+        assert_eq!(texture(&data, 1).unwrap(), Some(expected.clone()));
+        assert_eq!(
+            texture_from_reader(io::Cursor::new(&data), 1).unwrap(),
+            Some(expected)
+        );
+        assert_eq!(
+            texture_from_reader(io::Cursor::new(&data), 0)
+                .unwrap()
+                .map(|texture| texture.data),
+            Some(b"png".to_vec())
+        );
+        assert_eq!(texture(&data, 2).unwrap(), None);
+        assert_eq!(vendor(&data).unwrap(), parse(&data).unwrap().vendor);
+        assert_eq!(
+            texture_from_reader(io::Cursor::new(&data), 2).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(parse(b"PNG"), Err(FormatError::InvalidMagic)));
+
+        let data = sample();
+        for len in MAGIC.len()..data.len() {
+            /* Cutting into the vendor marker leaves a valid container without one */
+            let marker = data.len() - (EXT_SECTION.len() + 4 + 4 + 20 + 4 + 2);
+            if (marker..marker + EXT_SECTION.len()).contains(&len) {
+                continue;
+            }
+            assert!(parse(&data[..len]).is_err(), "length {} parsed", len);
+        }
+
+        let mut data = sample();
+        data[MAGIC.len() + 4 + 23 + 8 + 4 + 4] = 9;
+        assert!(matches!(
+            parse(&data),
+            Err(FormatError::UnknownTextureEncoding { index: 0, tag: 9 })
+        ));
+    }
+}
@@ -0,0 +1,334 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+use std::collections::HashMap;
+
+use super::model::{Node, NodeKind, Puppet, NO_THUMBNAIL};
+use super::{Container, FormatError, Texture};
+use crate::texture::{self, AlphaMode, Image};
+
+/// Longest edge of thumbnails rendered for puppets without an embedded one.
+pub const RENDERED_THUMBNAIL_SIZE: u32 = 256;
+
+/// A decoded puppet thumbnail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Thumbnail {
+    pub width: u32,
+    pub height: u32,
+    /// Straight (non-premultiplied) RGBA8 pixels, row by row from the top.
+    pub pixels: Vec<u8>,
+    /// Whether the thumbnail was rendered from the puppet rather than embedded in it.
+    pub rendered: bool,
+}
+
+fn decode(index: usize, texture: &Texture, alpha: AlphaMode) -> Result<Image, FormatError> {
+    texture::decode(texture)
+        .map(|image| image.into_alpha(alpha))
+        .map_err(|error| FormatError::Texture { index, error })
+}
+
+/* Decode the texture the puppet's metadata names as its thumbnail */
+pub(super) fn embedded(
+    index: u32,
+    texture: impl FnOnce(usize) -> Result<Option<Texture>, FormatError>,
+) -> Result<Option<Thumbnail>, FormatError> {
+    if index == NO_THUMBNAIL {
+        return Ok(None);
+    }
+
+    let index = index as usize;
+    let texture = match texture(index)? {
+        Some(texture) if !texture.data.is_empty() => texture,
+        _ => return Ok(None),
+    };
+
+    let image = decode(index, &texture, AlphaMode::Straight)?;
+    Ok(Some(Thumbnail {
+        width: image.width,
+        height: image.height,
+        pixels: image.pixels,
+        rendered: false,
+    }))
+}
+
+/* A 2D affine transform, [a, b, c, d, tx, ty] mapping (x, y) to (ax + cy + tx, bx + dy + ty) */
+#[derive(Clone, Copy)]
+struct Affine([f32; 6]);
+
+impl Affine {
+    const IDENTITY: Affine = Affine([1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+
+    fn from_node(node: &Node) -> Self {
+        let t = &node.transform;
+        let (sin, cos) = t.rot[2].sin_cos();
+        Affine([
+            cos * t.scale[0],
+            sin * t.scale[0],
+            -sin * t.scale[1],
+            cos * t.scale[1],
+            t.trans[0],
+            t.trans[1],
+        ])
+    }
+
+    fn then(self, local: Affine) -> Self {
+        let [a, b, c, d, tx, ty] = self.0;
+        let [la, lb, lc, ld, ltx, lty] = local.0;
+        Affine([
+            a * la + c * lb,
+            b * la + d * lb,
+            a * lc + c * ld,
+            b * lc + d * ld,
+            a * ltx + c * lty + tx,
+            b * ltx + d * lty + ty,
+        ])
+    }
+
+    fn apply(self, x: f32, y: f32) -> [f32; 2] {
+        let [a, b, c, d, tx, ty] = self.0;
+        [a * x + c * y + tx, b * x + d * y + ty]
+    }
+}
+
+/* A part ready to be rasterized, with its vertices in puppet space */
+struct DrawPart {
+    zsort: f32,
+    texture: usize,
+    verts: Vec<[f32; 2]>,
+    uvs: Vec<[f32; 2]>,
+    indices: Vec<u16>,
+    tint: [f32; 4],
+}
+
+fn collect_parts(node: &Node, parent: Affine, parent_zsort: f32, out: &mut Vec<DrawPart>) {
+    if !node.enabled {
+        return;
+    }
+
+    let world = if node.lock_to_root {
+        Affine::from_node(node)
+    } else {
+        parent.then(Affine::from_node(node))
+    };
+    let zsort = parent_zsort + node.zsort;
+
+    if let NodeKind::Part(part) = &node.kind {
+        let mesh = &part.mesh;
+        if let Some(&texture) = part.textures.first() {
+            out.push(DrawPart {
+                zsort,
+                texture: texture as usize,
+                verts: mesh
+                    .verts
+                    .chunks_exact(2)
+                    .map(|v| world.apply(v[0], v[1]))
+                    .collect(),
+                uvs: mesh.uvs.chunks_exact(2).map(|uv| [uv[0], uv[1]]).collect(),
+                indices: mesh.indices.clone(),
+                tint: [part.tint[0], part.tint[1], part.tint[2], part.opacity],
+            });
+        }
+    }
+
+    for child in node.children.iter() {
+        collect_parts(child, world, zsort, out);
+    }
+}
+
+fn edge(a: [f32; 2], b: [f32; 2], p: [f32; 2]) -> f32 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+/* Nearest-neighbour sample of a premultiplied image, returns RGBA in 0..1 */
+fn sample(image: &Image, uv: [f32; 2]) -> [f32; 4] {
+    let x = ((uv[0].clamp(0.0, 1.0) * image.width as f32) as u32).min(image.width - 1);
+    let y = ((uv[1].clamp(0.0, 1.0) * image.height as f32) as u32).min(image.height - 1);
+
+    image.pixel(x, y).map(|c| c as f32 / 255.0)
+}
+
+/// Render a rough thumbnail of a puppet on the CPU, without the Inochi2D runtime.
+///
+/// Parts are drawn in their rest pose with their first texture, normal blending and
+/// nearest-neighbour sampling. Masks, blend modes and parts whose textures can not be
+/// decoded are ignored.
+///
+/// # Example
+///
+/// ~~~no_run
+/// let container = Container::open("./puppets/Ada.inx")?;
+/// let thumbnail = format::render_thumbnail(&container, 128)?;
+///
+/// println!("{}x{}", thumbnail.width, thumbnail.height);
+/// ~~~
+///
+/// # Returns
+///
+/// - A thumbnail whose longest edge is `size` pixels.
+/// - `FormatError::NoThumbnail` if the puppet has nothing that can be drawn.
+/// - Otherwise a `FormatError` describing the error.
+///
+pub fn render_thumbnail(container: &Container, size: u32) -> Result<Thumbnail, FormatError> {
+    let puppet: Puppet = container.puppet()?;
+
+    let mut parts = Vec::new();
+    collect_parts(&puppet.nodes, Affine::IDENTITY, 0.0, &mut parts);
+
+    /* Decode each texture once, skipping parts whose texture is unusable */
+    let mut images = HashMap::new();
+    for part in parts.iter() {
+        images.entry(part.texture).or_insert_with(|| {
+            container
+                .textures
+                .get(part.texture)
+                .and_then(|texture| decode(part.texture, texture, AlphaMode::Premultiplied).ok())
+                .filter(|image| image.width > 0 && image.height > 0)
+        });
+    }
+    parts.retain(|part| matches!(images.get(&part.texture), Some(Some(_))));
+
+    let (mut min, mut max) = ([f32::MAX; 2], [f32::MIN; 2]);
+    for v in parts.iter().flat_map(|part| part.verts.iter()) {
+        min = [min[0].min(v[0]), min[1].min(v[1])];
+        max = [max[0].max(v[0]), max[1].max(v[1])];
+    }
+    let extent = (max[0] - min[0]).max(max[1] - min[1]);
+    if size == 0 || extent.is_nan() || extent <= 0.0 {
+        return Err(FormatError::NoThumbnail);
+    }
+
+    let scale = size as f32 / extent;
+    let width = (((max[0] - min[0]) * scale).ceil() as u32).clamp(1, size);
+    let height = (((max[1] - min[1]) * scale).ceil() as u32).clamp(1, size);
+    let mut canvas = vec![[0f32; 4]; width as usize * height as usize];
+
+    /* Inochi2D draws the highest zsort first */
+    parts.sort_by(|a, b| b.zsort.total_cmp(&a.zsort));
+    for part in parts.iter() {
+        let image = match images.get(&part.texture) {
+            Some(Some(image)) => image,
+            _ => continue,
+        };
+
+        for tri in part.indices.chunks_exact(3) {
+            let idx = [tri[0] as usize, tri[1] as usize, tri[2] as usize];
+            if idx
+                .iter()
+                .any(|&i| i >= part.verts.len() || i >= part.uvs.len())
+            {
+                continue;
+            }
+
+            let p = idx.map(|i| {
+                let v = part.verts[i];
+                [(v[0] - min[0]) * scale, (v[1] - min[1]) * scale]
+            });
+            let area = edge(p[0], p[1], p[2]);
+            if area == 0.0 {
+                continue;
+            }
+
+            let x0 = p.iter().map(|v| v[0]).fold(f32::MAX, f32::min).max(0.0) as u32;
+            let y0 = p.iter().map(|v| v[1]).fold(f32::MAX, f32::min).max(0.0) as u32;
+            let x1 = (p.iter().map(|v| v[0]).fold(f32::MIN, f32::max).ceil() as u32).min(width);
+            let y1 = (p.iter().map(|v| v[1]).fold(f32::MIN, f32::max).ceil() as u32).min(height);
+
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let c = [x as f32 + 0.5, y as f32 + 0.5];
+                    let w = [
+                        edge(p[1], p[2], c) / area,
+                        edge(p[2], p[0], c) / area,
+                        edge(p[0], p[1], c) / area,
+                    ];
+                    if w.iter().any(|&w| w < 0.0) {
+                        continue;
+                    }
+
+                    let uv = [0, 1]
+                        .map(|axis| (0..3).map(|k| part.uvs[idx[k]][axis] * w[k]).sum::<f32>());
+                    let src = sample(image, uv);
+                    let alpha = src[3] * part.tint[3];
+                    let dst = &mut canvas[y as usize * width as usize + x as usize];
+                    for ch in 0..3 {
+                        dst[ch] = src[ch] * part.tint[ch] * part.tint[3] + dst[ch] * (1.0 - alpha);
+                    }
+                    dst[3] = alpha + dst[3] * (1.0 - alpha);
+                }
+            }
+        }
+    }
+
+    let pixels = canvas
+        .iter()
+        .flat_map(|p| {
+            let unpremultiply = if p[3] > 0.0 { 1.0 / p[3] } else { 0.0 };
+            [
+                (p[0] * unpremultiply * 255.0).round().clamp(0.0, 255.0) as u8,
+                (p[1] * unpremultiply * 255.0).round().clamp(0.0, 255.0) as u8,
+                (p[2] * unpremultiply * 255.0).round().clamp(0.0, 255.0) as u8,
+                (p[3] * 255.0).round().clamp(0.0, 255.0) as u8,
+            ]
+        })
+        .collect();
+
+    Ok(Thumbnail {
+        width,
+        height,
+        pixels,
+        rendered: true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::TextureEncoding;
+    use crate::texture::tga::tests::tga;
+
+    #[test]
+    fn test_render() {
+        let json = r#"{
+            "meta": { "version": "1.0-alpha" },
+            "nodes": {
+                "uuid": 0, "name": "Root", "type": "Node", "enabled": true, "zsort": 0,
+                "transform": { "trans": [0, 0, 0], "rot": [0, 0, 0], "scale": [1, 1] },
+                "lockToRoot": false,
+                "children": [{
+                    "uuid": 1, "name": "Square", "type": "Part", "enabled": true, "zsort": 0,
+                    "transform": { "trans": [10, 10, 0], "rot": [0, 0, 0], "scale": [1, 1] },
+                    "lockToRoot": false,
+                    "mesh": {
+                        "verts": [0, 0, 4, 0, 4, 4, 0, 4],
+                        "uvs": [0, 0, 1, 0, 1, 1, 0, 1],
+                        "indices": [0, 1, 2, 0, 2, 3]
+                    },
+                    "textures": [0, 4294967295, 4294967295],
+                    "blend_mode": "Normal", "tint": [1, 1, 1], "mask_threshold": 0.5,
+                    "opacity": 1
+                }]
+            },
+            "param": []
+        }"#;
+        let container = Container {
+            json: json.into(),
+            textures: vec![Texture {
+                encoding: TextureEncoding::Tga,
+                data: tga(1, 1, &[[0, 0xFF, 0, 0xFF]]),
+            }],
+            vendor: Vec::new(),
+        };
+
+        let thumbnail = render_thumbnail(&container, 8).unwrap();
+        assert!(thumbnail.rendered);
+        assert_eq!((thumbnail.width, thumbnail.height), (8, 8));
+        assert!(thumbnail
+            .pixels
+            .chunks_exact(4)
+            .all(|p| p == [0, 0xFF, 0, 0xFF]));
+    }
+}
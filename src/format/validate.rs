@@ -0,0 +1,562 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use super::model::{BindingValue, Mesh, Node, NodeKind, Param, Puppet, NO_THUMBNAIL};
+use super::Texture;
+
+/* UUID Inochi2D uses for unset references */
+const INVALID_UUID: u32 = u32::MAX;
+
+/// How serious a `Diagnostic` is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// The puppet loads, but probably not as intended.
+    Warning,
+    /// The puppet is broken and will fail to load or render incorrectly.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Where in the puppet a `Diagnostic` was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    /// The puppet metadata.
+    Meta,
+    /// A node, with `path` being the node names from the root joined by `/`.
+    Node { path: String, uuid: u32 },
+    /// A parameter.
+    Param { name: String, uuid: u32 },
+    /// The binding at `index` of a parameter.
+    Binding { param: String, index: usize },
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Meta => write!(f, "meta"),
+            Location::Node { path, uuid } => write!(f, "{} ({})", path, uuid),
+            Location::Param { name, uuid } => write!(f, "param \"{}\" ({})", name, uuid),
+            Location::Binding { param, index } => {
+                write!(f, "param \"{}\" binding {}", param, index)
+            }
+        }
+    }
+}
+
+/// What is wrong with a mesh.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MeshProblem {
+    /// The vertex array has an odd number of coordinates.
+    OddVertexData,
+    /// The number of UVs does not match the number of vertices.
+    UvCount { vertices: usize, uvs: usize },
+    /// The index array is not a whole number of triangles.
+    PartialTriangle,
+    /// An index refers past the last vertex.
+    IndexOutOfRange { index: u16, vertices: usize },
+    /// The mesh has no triangles.
+    NoTriangles,
+    /// `count` triangles have no area.
+    ZeroArea { count: usize },
+}
+
+impl fmt::Display for MeshProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshProblem::OddVertexData => write!(f, "odd number of vertex coordinates"),
+            MeshProblem::UvCount { vertices, uvs } => {
+                write!(f, "{} vertices but {} UVs", vertices, uvs)
+            }
+            MeshProblem::PartialTriangle => write!(f, "index count is not a multiple of 3"),
+            MeshProblem::IndexOutOfRange { index, vertices } => {
+                write!(
+                    f,
+                    "index {} is out of range for {} vertices",
+                    index, vertices
+                )
+            }
+            MeshProblem::NoTriangles => write!(f, "no triangles"),
+            MeshProblem::ZeroArea { count } => write!(f, "{} zero-area triangles", count),
+        }
+    }
+}
+
+/// A problem found by `validate`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    /// More than one node uses `uuid`.
+    DuplicateUuid { uuid: u32 },
+    /// A binding refers to a node that does not exist.
+    DanglingBinding { node: u32 },
+    /// A node refers to a parameter that does not exist.
+    DanglingParam { param: u32 },
+    /// A mask refers to a node that does not exist.
+    MissingMaskSource { source: u32 },
+    /// A keypoint position is outside 0..1 or out of order.
+    KeypointOutOfRange { axis: usize, value: f32 },
+    /// The default value of a parameter is outside its range.
+    DefaultOutOfRange { axis: usize, value: f32 },
+    /// A binding's values do not have one entry per keypoint.
+    BindingShape {
+        expected: [usize; 2],
+        found: [usize; 2],
+    },
+    /// A deform binding has a different number of offsets than the node has vertices.
+    DeformVertexCount { expected: usize, found: usize },
+    /// A mesh is unusable.
+    DegenerateMesh(MeshProblem),
+    /// A texture slot is referenced but the container has no texture there.
+    MissingTexture { slot: u32 },
+    /// A parameter has no bindings and does nothing.
+    UnboundParameter,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::DuplicateUuid { uuid } => write!(f, "UUID {} is used by several nodes", uuid),
+            Issue::DanglingBinding { node } => write!(f, "bound node {} does not exist", node),
+            Issue::DanglingParam { param } => write!(f, "parameter {} does not exist", param),
+            Issue::MissingMaskSource { source } => {
+                write!(f, "mask source {} does not exist", source)
+            }
+            Issue::KeypointOutOfRange { axis, value } => write!(
+                f,
+                "keypoint {} on axis {} is out of range or out of order",
+                value, axis
+            ),
+            Issue::DefaultOutOfRange { axis, value } => {
+                write!(f, "default {} on axis {} is out of range", value, axis)
+            }
+            Issue::BindingShape { expected, found } => write!(
+                f,
+                "expected {}x{} values, found {}x{}",
+                expected[0], expected[1], found[0], found[1]
+            ),
+            Issue::DeformVertexCount { expected, found } => write!(
+                f,
+                "deform has {} offsets but the mesh has {} vertices",
+                found, expected
+            ),
+            Issue::DegenerateMesh(problem) => write!(f, "degenerate mesh: {}", problem),
+            Issue::MissingTexture { slot } => write!(f, "texture {} does not exist", slot),
+            Issue::UnboundParameter => write!(f, "parameter has no bindings"),
+        }
+    }
+}
+
+/// A single problem found in a puppet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub location: Location,
+    pub issue: Issue,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.severity, self.location, self.issue)
+    }
+}
+
+struct Validator<'a> {
+    puppet: &'a Puppet,
+    textures: Option<&'a [Texture]>,
+    nodes: HashMap<u32, &'a Node>,
+    params: HashSet<u32>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Validator<'a> {
+    fn report(&mut self, severity: Severity, location: Location, issue: Issue) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            location,
+            issue,
+        });
+    }
+
+    fn check_texture(&mut self, slot: u32, location: &Location) {
+        let textures = match self.textures {
+            Some(textures) => textures,
+            None => return,
+        };
+
+        let present = textures
+            .get(slot as usize)
+            .is_some_and(|texture| !texture.data.is_empty());
+        if !present {
+            self.report(
+                Severity::Error,
+                location.clone(),
+                Issue::MissingTexture { slot },
+            );
+        }
+    }
+
+    fn check_mesh(&mut self, mesh: &Mesh, location: &Location) {
+        let mut problems = Vec::new();
+        let vertices = mesh.vertex_count();
+
+        if !mesh.verts.len().is_multiple_of(2) {
+            problems.push((Severity::Error, MeshProblem::OddVertexData));
+        }
+        if mesh.uvs.len() / 2 != vertices {
+            problems.push((
+                Severity::Error,
+                MeshProblem::UvCount {
+                    vertices,
+                    uvs: mesh.uvs.len() / 2,
+                },
+            ));
+        }
+        if !mesh.indices.len().is_multiple_of(3) {
+            problems.push((Severity::Error, MeshProblem::PartialTriangle));
+        }
+
+        if let Some(&index) = mesh.indices.iter().find(|&&i| i as usize >= vertices) {
+            problems.push((
+                Severity::Error,
+                MeshProblem::IndexOutOfRange { index, vertices },
+            ));
+        } else if mesh.indices.len() < 3 {
+            problems.push((Severity::Warning, MeshProblem::NoTriangles));
+        } else {
+            let vertex = |i: u16| [mesh.verts[i as usize * 2], mesh.verts[i as usize * 2 + 1]];
+            let count = mesh
+                .indices
+                .chunks_exact(3)
+                .filter(|tri| {
+                    let [a, b, c] = [vertex(tri[0]), vertex(tri[1]), vertex(tri[2])];
+                    let area = (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]);
+                    area.abs() <= f32::EPSILON
+                })
+                .count();
+            if count > 0 {
+                problems.push((Severity::Warning, MeshProblem::ZeroArea { count }));
+            }
+        }
+
+        for (severity, problem) in problems {
+            self.report(severity, location.clone(), Issue::DegenerateMesh(problem));
+        }
+    }
+
+    fn check_node(&mut self, node: &'a Node, parent_path: &str, seen: &mut HashSet<u32>) {
+        let path = if parent_path.is_empty() {
+            node.name.clone()
+        } else {
+            format!("{}/{}", parent_path, node.name)
+        };
+        let location = Location::Node {
+            path: path.clone(),
+            uuid: node.uuid,
+        };
+
+        if !seen.insert(node.uuid) {
+            self.report(
+                Severity::Error,
+                location.clone(),
+                Issue::DuplicateUuid { uuid: node.uuid },
+            );
+        }
+
+        if let Some(mesh) = node.kind.mesh() {
+            self.check_mesh(mesh, &location);
+        }
+
+        for mask in node.kind.masks() {
+            if !self.nodes.contains_key(&mask.source) {
+                self.report(
+                    Severity::Error,
+                    location.clone(),
+                    Issue::MissingMaskSource {
+                        source: mask.source,
+                    },
+                );
+            }
+        }
+
+        for slot in node.texture_slots() {
+            self.check_texture(slot, &location);
+        }
+
+        match &node.kind {
+            NodeKind::SimplePhysics(physics)
+                if physics.param != INVALID_UUID && !self.params.contains(&physics.param) =>
+            {
+                self.report(
+                    Severity::Error,
+                    location.clone(),
+                    Issue::DanglingParam {
+                        param: physics.param,
+                    },
+                );
+            }
+            _ => (),
+        }
+
+        for child in node.children.iter() {
+            self.check_node(child, &path, seen);
+        }
+    }
+
+    fn check_param(&mut self, param: &Param) {
+        let location = Location::Param {
+            name: param.name.clone(),
+            uuid: param.uuid,
+        };
+
+        let axes = if param.is_vec2 { 2 } else { 1 };
+        for axis in 0..axes {
+            let mut previous = f32::NEG_INFINITY;
+            for &value in param.axis_points[axis].iter() {
+                if !(0.0..=1.0).contains(&value) || value < previous {
+                    self.report(
+                        Severity::Error,
+                        location.clone(),
+                        Issue::KeypointOutOfRange { axis, value },
+                    );
+                }
+                previous = value;
+            }
+
+            let value = param.defaults[axis];
+            let (low, high) = (
+                param.min[axis].min(param.max[axis]),
+                param.max[axis].max(param.min[axis]),
+            );
+            if !(low..=high).contains(&value) {
+                self.report(
+                    Severity::Warning,
+                    location.clone(),
+                    Issue::DefaultOutOfRange { axis, value },
+                );
+            }
+        }
+
+        if param.bindings.is_empty() {
+            self.report(Severity::Warning, location, Issue::UnboundParameter);
+        }
+
+        let expected = [
+            param.axis_points[0].len(),
+            param.axis_points[1].len().max(1),
+        ];
+        for (index, binding) in param.bindings.iter().enumerate() {
+            let location = Location::Binding {
+                param: param.name.clone(),
+                index,
+            };
+
+            let node = match self.nodes.get(&binding.node) {
+                Some(node) => *node,
+                None => {
+                    self.report(
+                        Severity::Error,
+                        location,
+                        Issue::DanglingBinding { node: binding.node },
+                    );
+                    continue;
+                }
+            };
+
+            let found = [
+                binding.values.len(),
+                binding.values.iter().map(Vec::len).max().unwrap_or(0),
+            ];
+            let ragged = binding.values.iter().any(|row| row.len() != found[1]);
+            if found != expected || ragged {
+                self.report(
+                    Severity::Error,
+                    location.clone(),
+                    Issue::BindingShape { expected, found },
+                );
+            }
+
+            let vertices = node.kind.mesh().map(Mesh::vertex_count);
+            for value in binding.values.iter().flatten() {
+                if let (BindingValue::Deform(offsets), Some(expected)) = (value, vertices) {
+                    /* Unset keypoints are stored without offsets */
+                    if !offsets.is_empty() && offsets.len() != expected {
+                        self.report(
+                            Severity::Error,
+                            location.clone(),
+                            Issue::DeformVertexCount {
+                                expected,
+                                found: offsets.len(),
+                            },
+                        );
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Check a puppet for problems that make it fail to load or misbehave.
+///
+/// Texture references are only checked if `textures` is given, use `Container::validate`
+/// to check a whole container.
+///
+/// # Example
+///
+/// ~~~no_run
+/// let container = Container::open("./puppets/Ada.inx")?;
+/// let puppet = container.puppet()?;
+///
+/// for diagnostic in format::validate(&puppet, Some(&container.textures)) {
+///     println!("{}", diagnostic);
+/// }
+/// ~~~
+///
+/// # Returns
+///
+/// Every problem found, in tree order for nodes followed by parameter order.
+///
+pub fn validate(puppet: &Puppet, textures: Option<&[Texture]>) -> Vec<Diagnostic> {
+    let mut validator = Validator {
+        puppet,
+        textures,
+        nodes: puppet.nodes.iter().map(|node| (node.uuid, node)).collect(),
+        params: puppet.param.iter().map(|param| param.uuid).collect(),
+        diagnostics: Vec::new(),
+    };
+
+    if puppet.meta.thumbnail_id != NO_THUMBNAIL {
+        validator.check_texture(puppet.meta.thumbnail_id, &Location::Meta);
+    }
+
+    validator.check_node(&puppet.nodes, "", &mut HashSet::new());
+    for param in validator.puppet.param.iter() {
+        validator.check_param(param);
+    }
+
+    validator.diagnostics
+}
+
+/// Check whether any of `diagnostics` is an error.
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::TextureEncoding;
+
+    const BROKEN: &str = r#"{
+        "meta": { "version": "1.0-alpha", "thumbnailId": 7 },
+        "nodes": {
+            "uuid": 1, "name": "Root", "type": "Node", "enabled": true, "zsort": 0,
+            "transform": { "trans": [0, 0, 0], "rot": [0, 0, 0], "scale": [1, 1] },
+            "lockToRoot": false,
+            "children": [{
+                "uuid": 2, "name": "Head", "type": "Part", "enabled": true, "zsort": 0,
+                "transform": { "trans": [0, 0, 0], "rot": [0, 0, 0], "scale": [1, 1] },
+                "lockToRoot": false,
+                "mesh": { "verts": [0, 0, 1, 0, 0, 1], "uvs": [0, 0, 1, 0], "indices": [0, 1, 3] },
+                "textures": [0, 4294967295, 4294967295], "blend_mode": "Normal",
+                "tint": [1, 1, 1], "mask_threshold": 0.5, "opacity": 1,
+                "masks": [{ "source": 9, "mode": "Mask" }]
+            }]
+        },
+        "param": [{
+            "uuid": 10, "name": "Head X", "is_vec2": false,
+            "min": [-1, -1], "max": [1, 1], "defaults": [0, 0],
+            "axis_points": [[0, 1.5], [0]],
+            "bindings": [{
+                "node": 5, "param_name": "transform.t.x",
+                "values": [[0], [1]], "isSet": [[true], [true]]
+            }, {
+                "node": 2, "param_name": "deform",
+                "values": [[[[0, 0]]]], "isSet": [[true]]
+            }]
+        }, {
+            "uuid": 11, "name": "Unused", "is_vec2": false,
+            "min": [0, 0], "max": [1, 1], "defaults": [2, 0],
+            "axis_points": [[0, 1], [0]]
+        }]
+    }"#;
+
+    #[test]
+    fn test_validate() {
+        let puppet = Puppet::from_json(BROKEN).unwrap();
+        let textures = [Texture {
+            encoding: TextureEncoding::Png,
+            data: b"png".to_vec(),
+        }];
+
+        let issues: Vec<_> = validate(&puppet, Some(&textures))
+            .into_iter()
+            .map(|diagnostic| diagnostic.issue)
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                Issue::MissingTexture { slot: 7 },
+                Issue::DegenerateMesh(MeshProblem::UvCount {
+                    vertices: 3,
+                    uvs: 2
+                }),
+                Issue::DegenerateMesh(MeshProblem::IndexOutOfRange {
+                    index: 3,
+                    vertices: 3
+                }),
+                Issue::MissingMaskSource { source: 9 },
+                Issue::KeypointOutOfRange {
+                    axis: 0,
+                    value: 1.5
+                },
+                Issue::DanglingBinding { node: 5 },
+                Issue::BindingShape {
+                    expected: [2, 1],
+                    found: [1, 1]
+                },
+                Issue::DeformVertexCount {
+                    expected: 3,
+                    found: 1
+                },
+                Issue::DefaultOutOfRange {
+                    axis: 0,
+                    value: 2.0
+                },
+                Issue::UnboundParameter,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_locations() {
+        let puppet = Puppet::from_json(BROKEN).unwrap();
+        let diagnostics = validate(&puppet, None);
+
+        assert!(has_errors(&diagnostics));
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "error: Root/Head (2): degenerate mesh: 3 vertices but 2 UVs"
+        );
+        assert_eq!(
+            diagnostics.last().unwrap().to_string(),
+            "warning: param \"Unused\" (11): parameter has no bindings"
+        );
+    }
+}
@@ -0,0 +1,139 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+use std::io::Write;
+
+use super::{Container, FormatError, EXT_SECTION, MAGIC, TEX_SECTION};
+
+fn len_u32(len: usize, what: &'static str) -> Result<[u8; 4], FormatError> {
+    u32::try_from(len)
+        .map(u32::to_be_bytes)
+        .map_err(|_| FormatError::TooLarge { what, len })
+}
+
+fn blob<W: Write>(out: &mut W, data: &[u8], what: &'static str) -> Result<(), FormatError> {
+    out.write_all(&len_u32(data.len(), what)?)?;
+    out.write_all(data)?;
+    Ok(())
+}
+
+pub(super) fn write<W: Write>(container: &Container, out: &mut W) -> Result<(), FormatError> {
+    out.write_all(MAGIC)?;
+    blob(out, container.json.as_bytes(), "puppet JSON")?;
+
+    out.write_all(TEX_SECTION)?;
+    out.write_all(&len_u32(container.textures.len(), "texture section")?)?;
+    for texture in container.textures.iter() {
+        out.write_all(&len_u32(texture.data.len(), "texture")?)?;
+        out.write_all(&[texture.encoding.tag()])?;
+        out.write_all(&texture.data)?;
+    }
+
+    /* The runtime treats a missing vendor section as empty, so only write it when needed */
+    if !container.vendor.is_empty() {
+        out.write_all(EXT_SECTION)?;
+        out.write_all(&len_u32(container.vendor.len(), "vendor section")?)?;
+        for vendor in container.vendor.iter() {
+            blob(out, vendor.name.as_bytes(), "vendor section name")?;
+            blob(out, &vendor.payload, "vendor payload")?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::format::{Container, Texture, TextureEncoding, VendorData};
+
+    #[test]
+    fn test_round_trip() {
+        let container = Container {
+            json: r#"{"meta":{"name":"Ada"}}"#.into(),
+            textures: vec![
+                Texture {
+                    encoding: TextureEncoding::Png,
+                    data: b"png".to_vec(),
+                },
+                Texture {
+                    encoding: TextureEncoding::Tga,
+                    data: Vec::new(),
+                },
+                Texture {
+                    encoding: TextureEncoding::Bc7,
+                    data: vec![0; 16],
+                },
+            ],
+            vendor: vec![VendorData {
+                name: "com.example.tracking".into(),
+                payload: b"{}".to_vec(),
+            }],
+        };
+
+        let data = container.to_bytes().unwrap();
+        let parsed = Container::parse(&data).unwrap();
+        assert_eq!(parsed, container);
+        assert_eq!(parsed.to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    fn test_no_vendor_section() {
+        let container = Container {
+            json: "{}".into(),
+            ..Container::default()
+        };
+
+        let data = container.to_bytes().unwrap();
+        assert_eq!(data.len(), 8 + 4 + 2 + 8 + 4);
+        assert_eq!(Container::parse(&data).unwrap(), container);
+    }
+
+    #[test]
+    fn test_vendor_data() {
+        let mut container = Container {
+            json: "{}".into(),
+            ..Container::default()
+        };
+
+        assert_eq!(container.set_vendor_data("a", b"1".to_vec()), None);
+        assert_eq!(container.set_vendor_data("b", b"2".to_vec()), None);
+        assert_eq!(
+            container.set_vendor_data("a", b"3".to_vec()),
+            Some(b"1".to_vec())
+        );
+
+        let parsed = Container::parse(&container.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.vendor_names().collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(parsed.vendor_data("a"), Some(&b"3"[..]));
+
+        assert_eq!(container.remove_vendor_data("a"), Some(b"3".to_vec()));
+        assert_eq!(container.vendor_data("a"), None);
+    }
+
+    #[test]
+    fn test_save_replaces() {
+        let dir = std::env::temp_dir().join(format!("inochi2d-save-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Ada.inx");
+        std::fs::write(&path, b"old").unwrap();
+
+        let container = Container {
+            json: "{}".into(),
+            ..Container::default()
+        };
+        container.save(&path).unwrap();
+        assert_eq!(Container::open(&path).unwrap(), container);
+        /* Only the puppet is left, the temporary file was renamed over it */
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        /* A failed save leaves no temporary file behind */
+        assert!(container.save(dir.join("missing").join("Ada.inx")).is_err());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
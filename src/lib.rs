@@ -10,28 +10,88 @@
 /*!
     An attempted idiomatic wrapper for [Inochi2D](https://github.com/Inochi2D/inochi2d).
 
+    Everything that drives the Inochi2D runtime is behind the default `runtime` feature.
+    Without it only `asset`, `format`, `rights` and `texture` are built, which don't need the
+    C library or the D toolchain.
+
 */
 
 #[macro_use]
 mod macros;
 
+#[cfg(feature = "runtime")]
 mod ffi;
 
+pub mod asset;
+#[cfg(feature = "runtime")]
 pub mod camera;
+#[cfg(feature = "runtime")]
 pub mod core;
+#[cfg(feature = "runtime")]
+pub mod error;
+#[cfg(feature = "runtime")]
+pub mod expression;
+#[cfg(feature = "runtime")]
+pub mod filter;
+pub mod format;
+#[cfg(feature = "runtime")]
+pub mod loader;
+#[cfg(feature = "runtime")]
+pub mod mixer;
+#[cfg(feature = "runtime")]
+pub mod param;
+#[cfg(feature = "runtime")]
 pub mod puppet;
+#[cfg(feature = "runtime")]
+pub mod recording;
+#[cfg(feature = "runtime")]
+pub mod reload;
+pub mod rights;
 #[cfg(feature = "opengl")]
 pub mod scene;
+pub mod texture;
 
+#[cfg(feature = "runtime")]
+use crate::asset::AssetSource;
+#[cfg(feature = "runtime")]
 use crate::core::Inochi2D;
-use std::path::PathBuf;
+#[cfg(feature = "runtime")]
+use crate::format::LoadLimits;
+#[cfg(feature = "runtime")]
+use crate::rights::RightsPolicy;
+#[cfg(feature = "runtime")]
+use std::io::Read;
+#[cfg(feature = "runtime")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "runtime")]
+use std::sync::Arc;
+#[cfg(feature = "runtime")]
+use std::time::Duration;
+
+#[cfg(feature = "runtime")]
+pub use crate::error::Inochi2DError;
 
-pub type Result<T> = std::result::Result<T, String>;
+#[cfg(feature = "runtime")]
+pub type Result<T> = std::result::Result<T, Inochi2DError>;
+
+/* A puppet queued on the builder, loaded once the context exists */
+#[cfg(feature = "runtime")]
+enum PendingPuppet {
+    Path(PathBuf),
+    Bytes(Vec<u8>, Option<String>),
+    Reader(Box<dyn Read>, Option<String>),
+}
 
+#[cfg(feature = "runtime")]
 pub struct Inochi2DBuilder {
     viewport_size: (i32, i32),
     time_func: Option<extern "C" fn() -> f64>,
-    puppets: Vec<PathBuf>,
+    puppets: Vec<PendingPuppet>,
+    assets: Option<Arc<dyn AssetSource>>,
+    hot_reload: Option<Duration>,
+    rights_policy: Option<RightsPolicy>,
+    auto_migrate: bool,
+    load_limits: Option<LoadLimits>,
 }
 
 #[cfg(feature = "monotonic")]
@@ -52,6 +112,7 @@ pub extern "C" fn MONOTONIC_CLOCK() -> f64 {
     }
 }
 
+#[cfg(feature = "runtime")]
 impl<'a> Inochi2DBuilder {
     /// Creates a new Inochi2D context builder.
     ///
@@ -78,6 +139,11 @@ impl<'a> Inochi2DBuilder {
             viewport_size: (800, 600),
             time_func: None,
             puppets: Vec::new(),
+            assets: None,
+            hot_reload: None,
+            rights_policy: None,
+            auto_migrate: false,
+            load_limits: None,
         }
     }
 
@@ -100,6 +166,66 @@ impl<'a> Inochi2DBuilder {
         self
     }
 
+    /// Set the asset source puppets added with `puppet` are resolved through.
+    ///
+    /// By default puppets are loaded from the filesystem.
+    ///
+    /// # Example
+    /// ~~~no_run
+    /// let ctx = Inochi2DBuilder::new()
+    ///     .assets(ZipSource::new(File::open("./content.pak")?)?)
+    ///     .puppet("puppets/Ada.inx")
+    ///     .build()
+    ///     .expect("Unable to create Inochi2D context");
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// The current `Inochi2DBuilder` instance.
+    ///
+    pub fn assets(mut self, source: impl AssetSource + 'static) -> Inochi2DBuilder {
+        self.assets = Some(Arc::new(source));
+        self
+    }
+
+    /// Upgrade older puppets to the current schema as they are loaded.
+    ///
+    /// # Example
+    /// ~~~no_run
+    /// let ctx = Inochi2DBuilder::new()
+    ///     .auto_migrate(true)
+    ///     .build()
+    ///     .expect("Unable to create Inochi2D context");
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// The current `Inochi2DBuilder` instance.
+    ///
+    pub fn auto_migrate(mut self, migrate: bool) -> Inochi2DBuilder {
+        self.auto_migrate = migrate;
+        self
+    }
+
+    /// Refuse puppets that exceed resource limits before they reach Inochi2D.
+    ///
+    /// # Example
+    /// ~~~no_run
+    /// let ctx = Inochi2DBuilder::new()
+    ///     .load_limits(LoadLimits::default())
+    ///     .build()
+    ///     .expect("Unable to create Inochi2D context");
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// The current `Inochi2DBuilder` instance.
+    ///
+    pub fn load_limits(mut self, limits: LoadLimits) -> Inochi2DBuilder {
+        self.load_limits = Some(limits);
+        self
+    }
+
     /// Add a puppet to be loaded.
     ///
     /// # Example
@@ -114,8 +240,94 @@ impl<'a> Inochi2DBuilder {
     ///
     /// The current `Inochi2DBuilder` instance.
     ///
-    pub fn puppet(mut self, puppet: PathBuf) -> Inochi2DBuilder {
-        self.puppets.push(puppet);
+    pub fn puppet(mut self, puppet: impl AsRef<Path>) -> Inochi2DBuilder {
+        self.puppets
+            .push(PendingPuppet::Path(puppet.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Add a puppet to be loaded from an in-memory buffer.
+    ///
+    /// # Example
+    /// ~~~no_run
+    /// let ctx = Inochi2DBuilder::new()
+    ///     .puppet_bytes(include_bytes!("./puppets/Ada.inx"), Some("Ada".into()))
+    ///     .build()
+    ///     .expect("Unable to create Inochi2D context");
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// The current `Inochi2DBuilder` instance.
+    ///
+    pub fn puppet_bytes(
+        mut self,
+        buffer: impl Into<Vec<u8>>,
+        name: Option<String>,
+    ) -> Inochi2DBuilder {
+        self.puppets.push(PendingPuppet::Bytes(buffer.into(), name));
+        self
+    }
+
+    /// Add a puppet to be loaded from a reader, the reader is consumed when `build` is called.
+    ///
+    /// # Example
+    /// ~~~no_run
+    /// let ctx = Inochi2DBuilder::new()
+    ///     .puppet_reader(File::open("./puppets/Ada.inx")?, Some("Ada".into()))
+    ///     .build()
+    ///     .expect("Unable to create Inochi2D context");
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// The current `Inochi2DBuilder` instance.
+    ///
+    pub fn puppet_reader<R: Read + 'static>(
+        mut self,
+        reader: R,
+        name: Option<String>,
+    ) -> Inochi2DBuilder {
+        self.puppets
+            .push(PendingPuppet::Reader(Box::new(reader), name));
+        self
+    }
+
+    /// Reload puppets when their files change, checking at most once per `interval`.
+    ///
+    /// # Example
+    /// ~~~no_run
+    /// let ctx = Inochi2DBuilder::new()
+    ///     .hot_reload(Duration::from_millis(500))
+    ///     .build()
+    ///     .expect("Unable to create Inochi2D context");
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// The current `Inochi2DBuilder` instance.
+    ///
+    pub fn hot_reload(mut self, interval: Duration) -> Inochi2DBuilder {
+        self.hot_reload = Some(interval);
+        self
+    }
+
+    /// Check puppets against a usage-rights policy as they are loaded.
+    ///
+    /// # Example
+    /// ~~~no_run
+    /// let ctx = Inochi2DBuilder::new()
+    ///     .rights_policy(RightsPolicy::default())
+    ///     .build()
+    ///     .expect("Unable to create Inochi2D context");
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// The current `Inochi2DBuilder` instance.
+    ///
+    pub fn rights_policy(mut self, policy: RightsPolicy) -> Inochi2DBuilder {
+        self.rights_policy = Some(policy);
         self
     }
 
@@ -152,23 +364,33 @@ impl<'a> Inochi2DBuilder {
     /// # Returns
     ///
     /// - If initialization was successful a `Inochi2D` context.
-    /// - If an error occurred a string indicating the error will be returned.
+    /// - If an error occurred an `Inochi2DError` describing the error will be returned.
     ///
     pub fn build(self) -> Result<Inochi2D> {
-        if self.time_func.is_none() {
-            Err("timing mut be called before build!".into())
-        } else {
-            let mut ctx = Inochi2D::new(
-                self.time_func.unwrap(),
-                self.viewport_size.0,
-                self.viewport_size.1,
-            );
-
-            for p in self.puppets {
-                ctx.add_puppet(p)?;
+        let time_func = self.time_func.ok_or(Inochi2DError::MissingTimingFunction)?;
+
+        let mut ctx = Inochi2D::new(time_func, self.viewport_size.0, self.viewport_size.1);
+
+        if let Some(assets) = self.assets {
+            ctx.set_asset_source(assets);
+        }
+
+        ctx.set_rights_policy(self.rights_policy);
+        ctx.set_auto_migrate(self.auto_migrate);
+        ctx.set_load_limits(self.load_limits);
+
+        for p in self.puppets {
+            match p {
+                PendingPuppet::Path(path) => ctx.add_puppet(path)?,
+                PendingPuppet::Bytes(buffer, name) => ctx.add_puppet_bytes(&buffer, name)?,
+                PendingPuppet::Reader(reader, name) => ctx.add_puppet_reader(reader, name)?,
             }
+        }
 
-            Ok(ctx)
+        if let Some(interval) = self.hot_reload {
+            ctx.enable_hot_reload(interval);
         }
+
+        Ok(ctx)
     }
 }
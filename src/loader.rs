@@ -0,0 +1,323 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+#[cfg(feature = "logging")]
+use tracing::debug;
+
+use crate::asset::AssetSource;
+use crate::format::{FormatError, Limit};
+use crate::puppet::{Inochi2DPuppet, LoadOptions, PreparedPuppet, PuppetSource};
+use crate::{Inochi2DError, Result};
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+fn thread_died() -> Inochi2DError {
+    Inochi2DError::Io(std::io::Error::other("Puppet loader thread died"))
+}
+
+/// A snapshot of the progress of a background puppet load.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadProgress {
+    /// Number of bytes read from the asset source so far.
+    pub bytes_read: u64,
+    /// Total size of the puppet in bytes, if the asset source knows it.
+    pub bytes_total: Option<u64>,
+    /// Whether the puppet data has been read and checked, and is ready to be finished.
+    pub ready: bool,
+}
+
+impl LoadProgress {
+    /// Get the fraction of the puppet that has been read, if the total size is known.
+    pub fn fraction(&self) -> Option<f32> {
+        match self.bytes_total {
+            _ if self.ready => Some(1.0),
+            Some(0) => Some(1.0),
+            Some(total) => Some((self.bytes_read as f64 / total as f64).min(1.0) as f32),
+            None => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct SharedProgress {
+    bytes_read: AtomicU64,
+    bytes_total: AtomicU64,
+    total_known: AtomicBool,
+    ready: AtomicBool,
+}
+
+/// A puppet being loaded on a background thread.
+///
+/// Reading the puppet, checking it against the load limits and usage rights policy and
+/// migrating it happens on a worker thread. The final step of handing the data to Inochi2D,
+/// which decodes the textures and creates GPU resources, is done by `poll` or `wait` and must
+/// be called from the thread that owns the `Inochi2D` context.
+///
+/// Inochi2D only accepts encoded puppet data, so the texture decoding it does in that final
+/// step can't be moved to the worker.
+///
+pub struct PuppetLoader {
+    id: PathBuf,
+    progress: Arc<SharedProgress>,
+    receiver: Receiver<Result<PreparedPuppet<'static>>>,
+    done: bool,
+}
+
+impl PuppetLoader {
+    /// Start loading the puppet `id` from `source` on a background thread.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let source = Arc::new(FileSystemSource::default());
+    /// let mut loader = PuppetLoader::spawn(source, "./puppets/Ada.inx");
+    ///
+    /// loop {
+    ///     if let Some(puppet) = loader.poll() {
+    ///         break puppet?;
+    ///     }
+    ///     println!("{:?}", loader.progress());
+    /// }
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// A new `PuppetLoader`.
+    ///
+    pub fn spawn(source: Arc<dyn AssetSource>, id: impl AsRef<Path>) -> Self {
+        Self::spawn_with(source, id, LoadOptions::default())
+    }
+
+    /* As `spawn`, checking the puppet against `options` and stopping reads past its file size limit */
+    pub(crate) fn spawn_with(
+        source: Arc<dyn AssetSource>,
+        id: impl AsRef<Path>,
+        options: LoadOptions,
+    ) -> Self {
+        let id = id.as_ref().to_path_buf();
+        let max_size = options.limits.as_ref().map(|limits| limits.max_file_size);
+        let progress = Arc::new(SharedProgress::default());
+        let (sender, receiver) = mpsc::channel();
+
+        #[cfg(feature = "logging")]
+        debug!("Loading puppet {} in the background", id.display());
+
+        let worker_id = id.clone();
+        let worker_progress = progress.clone();
+        thread::spawn(move || {
+            let res = Self::read(source.as_ref(), &worker_id, max_size, &worker_progress).and_then(
+                |data| {
+                    let source = PuppetSource::Path(worker_id.clone());
+                    PreparedPuppet::new(data.into(), source, &options)
+                },
+            );
+            worker_progress.ready.store(res.is_ok(), Ordering::Release);
+            /* The loader may have been dropped, in which case nobody cares */
+            let _ = sender.send(res);
+        });
+
+        PuppetLoader {
+            id,
+            progress,
+            receiver,
+            done: false,
+        }
+    }
+
+    fn read(
+        source: &dyn AssetSource,
+        id: &Path,
+        max_size: Option<usize>,
+        progress: &SharedProgress,
+    ) -> Result<Vec<u8>> {
+        let (mut reader, len) = source
+            .open(id)
+            .map_err(|err| Inochi2DError::from_asset_io(id, err))?;
+        let too_large = |size: u64| match max_size {
+            Some(max) if size > max as u64 => Err(FormatError::LimitExceeded {
+                limit: Limit::FileSize,
+                value: size as usize,
+                max,
+            }),
+            _ => Ok(()),
+        };
+
+        if let Some(len) = len {
+            too_large(len)?;
+            progress.bytes_total.store(len, Ordering::Relaxed);
+            progress.total_known.store(true, Ordering::Release);
+        }
+
+        let mut buffer = Vec::with_capacity(len.unwrap_or(0) as usize);
+        let mut chunk = vec![0u8; READ_CHUNK_SIZE];
+        loop {
+            let count = match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(count) => count,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+
+            buffer.extend_from_slice(&chunk[..count]);
+            progress
+                .bytes_read
+                .fetch_add(count as u64, Ordering::Relaxed);
+            /* Sources that don't know their size are cut off once they pass the limit */
+            too_large(buffer.len() as u64)?;
+        }
+
+        Ok(buffer)
+    }
+
+    /// Get the identifier of the puppet being loaded.
+    pub fn id(&self) -> &Path {
+        &self.id
+    }
+
+    /// Get the current progress of the load.
+    pub fn progress(&self) -> LoadProgress {
+        LoadProgress {
+            bytes_read: self.progress.bytes_read.load(Ordering::Relaxed),
+            bytes_total: if self.progress.total_known.load(Ordering::Acquire) {
+                Some(self.progress.bytes_total.load(Ordering::Relaxed))
+            } else {
+                None
+            },
+            ready: self.progress.ready.load(Ordering::Acquire),
+        }
+    }
+
+    /// Check on the load, finishing it if the puppet data has been read.
+    ///
+    /// This must be called from the thread that owns the `Inochi2D` context.
+    ///
+    /// # Returns
+    ///
+    /// - `None` if the puppet is still being read or checked, or the result was already
+    ///   returned.
+    /// - `Some` with the loaded puppet or the error that occurred otherwise.
+    ///
+    pub fn poll(&mut self) -> Option<Result<Inochi2DPuppet>> {
+        if self.done {
+            return None;
+        }
+
+        match self.receiver.try_recv() {
+            Ok(res) => Some(self.finish(res)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(self.finish(Err(thread_died()))),
+        }
+    }
+
+    /// Block until the puppet data has been read and checked, then finish the load.
+    ///
+    /// This must be called from the thread that owns the `Inochi2D` context.
+    ///
+    pub fn wait(mut self) -> Result<Inochi2DPuppet> {
+        let res = self.receiver.recv().unwrap_or_else(|_| Err(thread_died()));
+
+        self.finish(res)
+    }
+
+    fn finish(&mut self, res: Result<PreparedPuppet<'static>>) -> Result<Inochi2DPuppet> {
+        self.done = true;
+
+        #[cfg(feature = "logging")]
+        debug!("Finishing background load of {}", self.id.display());
+
+        Inochi2DPuppet::from_prepared(res?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::MemorySource;
+    use crate::format::{Container, LoadLimits};
+
+    #[test]
+    fn test_background_read() {
+        let mut source = MemorySource::new();
+        source.insert("Ada.inx", vec![0u8; READ_CHUNK_SIZE * 2 + 5]);
+
+        let progress = SharedProgress::default();
+        let data = PuppetLoader::read(&source, Path::new("Ada.inx"), None, &progress).unwrap();
+
+        assert_eq!(data.len(), READ_CHUNK_SIZE * 2 + 5);
+        assert_eq!(
+            progress.bytes_read.load(Ordering::Relaxed),
+            data.len() as u64
+        );
+        assert_eq!(
+            progress.bytes_total.load(Ordering::Relaxed),
+            data.len() as u64
+        );
+    }
+
+    #[test]
+    fn test_background_limits() {
+        let mut source = MemorySource::new();
+        source.insert("Ada.inx", vec![0u8; READ_CHUNK_SIZE * 4]);
+
+        let progress = SharedProgress::default();
+        let res = PuppetLoader::read(&source, Path::new("Ada.inx"), Some(1024), &progress);
+
+        assert!(matches!(
+            res,
+            Err(Inochi2DError::Format(FormatError::LimitExceeded {
+                limit: Limit::FileSize,
+                ..
+            }))
+        ));
+        assert_eq!(progress.bytes_read.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_background_checks() {
+        let mut source = MemorySource::new();
+        let data = Container {
+            json: "{}".into(),
+            ..Container::default()
+        };
+        source.insert("Ada.inx", data.to_bytes().unwrap());
+        let options = LoadOptions {
+            limits: Some(LoadLimits {
+                max_json_depth: 0,
+                ..LoadLimits::default()
+            }),
+            ..LoadOptions::default()
+        };
+
+        /* The limits are checked on the worker, so nothing reaches Inochi2D */
+        let loader = PuppetLoader::spawn_with(Arc::new(source), "Ada.inx", options);
+        assert!(matches!(
+            loader.wait(),
+            Err(Inochi2DError::Format(FormatError::LimitExceeded {
+                limit: Limit::JsonDepth,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_background_missing() {
+        let source = Arc::new(MemorySource::new());
+        let loader = PuppetLoader::spawn(source, "Ada.inx");
+
+        match loader.wait() {
+            Err(Inochi2DError::InvalidPath(path)) => assert_eq!(path, Path::new("Ada.inx")),
+            _ => panic!("expected InvalidPath"),
+        }
+    }
+}
@@ -1,3 +1,4 @@
+#[cfg(feature = "runtime")]
 macro_rules! create_opaque_type {
     ($type_name:ident) => {
         #[cfg(feature = "nightly")]
@@ -12,3 +13,59 @@ macro_rules! create_opaque_type {
         }
     };
 }
+
+/* A string valued enum that keeps values it does not know about */
+macro_rules! string_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $type_name:ident {
+            $($(#[$variant_meta:meta])* $variant:ident => $value:literal,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        pub enum $type_name {
+            $($(#[$variant_meta])* $variant,)*
+            /// A value not known to this crate.
+            Other(String),
+        }
+
+        impl $type_name {
+            /// Get the string stored in the puppet JSON.
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $($type_name::$variant => $value,)*
+                    $type_name::Other(value) => value,
+                }
+            }
+        }
+
+        impl From<&str> for $type_name {
+            fn from(value: &str) -> Self {
+                match value {
+                    $($value => $type_name::$variant,)*
+                    _ => $type_name::Other(value.to_string()),
+                }
+            }
+        }
+
+        impl std::fmt::Display for $type_name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl serde::Serialize for $type_name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.as_str())
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $type_name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let value = String::deserialize(deserializer)?;
+                Ok($type_name::from(value.as_str()))
+            }
+        }
+    };
+}
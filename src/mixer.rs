@@ -0,0 +1,277 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+use std::collections::HashMap;
+
+use crate::param::ParamValue;
+
+/// How a mixer layer combines with the layers below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlendMode {
+    /// Blend towards the layer's values by its weight, replacing them at full weight.
+    #[default]
+    Override,
+    /// Add the layer's values, scaled by its weight, as offsets.
+    Additive,
+}
+
+/// A named set of parameter values in a `ParamMixer`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    name: String,
+    weight: f32,
+    mode: BlendMode,
+    priority: i32,
+    values: HashMap<String, ParamValue>,
+}
+
+impl Layer {
+    /// Get the name of the layer.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get how strongly the layer applies, from 0 to 1.
+    pub fn weight(&self) -> f32 {
+        self.weight
+    }
+
+    /// Set how strongly the layer applies, clamped to 0..1.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// /* Fade the manual override in from a UI slider */
+    /// if let Some(layer) = puppet.mixer_mut().layer_mut("manual") {
+    ///     layer.set_weight(slider.value());
+    /// }
+    /// ~~~
+    ///
+    pub fn set_weight(&mut self, weight: f32) {
+        self.weight = if weight.is_nan() {
+            0.0
+        } else {
+            weight.clamp(0.0, 1.0)
+        };
+    }
+
+    /// Get how the layer combines with the layers below it.
+    pub fn mode(&self) -> BlendMode {
+        self.mode
+    }
+
+    /// Set how the layer combines with the layers below it.
+    pub fn set_mode(&mut self, mode: BlendMode) {
+        self.mode = mode;
+    }
+
+    /// Get the priority of the layer, layers with a higher priority apply on top.
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Set the value the layer gives the parameter called `name`.
+    ///
+    /// A `ParamValue::Scalar` only affects the X axis, so a layer can drive one axis of a
+    /// 2D parameter.
+    ///
+    pub fn set(&mut self, name: &str, value: ParamValue) {
+        match self.values.get_mut(name) {
+            Some(current) => *current = value,
+            None => {
+                self.values.insert(name.to_string(), value);
+            }
+        }
+    }
+
+    /// Get the value the layer gives the parameter called `name`.
+    pub fn get(&self, name: &str) -> Option<ParamValue> {
+        self.values.get(name).copied()
+    }
+
+    /// Stop the layer from affecting the parameter called `name`.
+    pub fn unset(&mut self, name: &str) -> Option<ParamValue> {
+        self.values.remove(name)
+    }
+
+    /// Stop the layer from affecting any parameter.
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    /* Combine `below` with this layer's `value` */
+    fn blend(&self, below: f32, value: f32) -> f32 {
+        match self.mode {
+            BlendMode::Override => below + (value - below) * self.weight,
+            BlendMode::Additive => below + value * self.weight,
+        }
+    }
+}
+
+/// Blends parameter values from several sources, such as face tracking, scripted
+/// animations and manual overrides.
+///
+/// Layers apply from the lowest priority to the highest, starting from each parameter's
+/// default value, and layers with equal priority apply in the order they were added. The
+/// mixer of an `Inochi2DPuppet` is resolved on every `Inochi2DPuppet::update`, parameters
+/// no layer has a value for are left as they are.
+///
+/// # Example
+///
+/// ~~~no_run
+/// let mixer = puppet.mixer_mut();
+/// mixer.add_layer("tracking", BlendMode::Override, 0);
+/// mixer.add_layer("breathing", BlendMode::Additive, 10);
+/// mixer.add_layer("manual", BlendMode::Override, 100).set_weight(0.0);
+///
+/// loop {
+///     let tracking = puppet.mixer_mut().layer_mut("tracking").unwrap();
+///     tracking.set("HeadX", ParamValue::Scalar(face.yaw));
+///
+///     puppet.update();
+/// }
+/// ~~~
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParamMixer {
+    /* Sorted by priority, stable for equal priorities */
+    layers: Vec<Layer>,
+}
+
+impl ParamMixer {
+    /// Create a mixer without layers.
+    pub fn new() -> Self {
+        ParamMixer::default()
+    }
+
+    /// Add a layer at full weight, replacing any layer called `name`.
+    ///
+    /// # Returns
+    ///
+    /// The new layer, to set its values or weight.
+    ///
+    pub fn add_layer(&mut self, name: &str, mode: BlendMode, priority: i32) -> &mut Layer {
+        self.remove_layer(name);
+
+        let index = self
+            .layers
+            .partition_point(|layer| layer.priority <= priority);
+        self.layers.insert(
+            index,
+            Layer {
+                name: name.to_string(),
+                weight: 1.0,
+                mode,
+                priority,
+                values: HashMap::new(),
+            },
+        );
+
+        &mut self.layers[index]
+    }
+
+    /// Remove the layer called `name`.
+    pub fn remove_layer(&mut self, name: &str) -> Option<Layer> {
+        let index = self.layers.iter().position(|layer| layer.name == name)?;
+
+        Some(self.layers.remove(index))
+    }
+
+    /// Get the layer called `name`.
+    pub fn layer(&self, name: &str) -> Option<&Layer> {
+        self.layers.iter().find(|layer| layer.name == name)
+    }
+
+    /// Get the layer called `name` to change its values or weight.
+    pub fn layer_mut(&mut self, name: &str) -> Option<&mut Layer> {
+        self.layers.iter_mut().find(|layer| layer.name == name)
+    }
+
+    /// Get the layers, from the lowest priority to the highest.
+    pub fn layers(&self) -> impl Iterator<Item = &Layer> {
+        self.layers.iter()
+    }
+
+    /// Check whether the mixer has no layers.
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// Resolve every layer into final parameter values.
+    ///
+    /// `default` gives the starting value of a parameter, or `None` if the puppet has no
+    /// parameter by that name, in which case the parameter is skipped.
+    ///
+    pub fn resolve(
+        &self,
+        mut default: impl FnMut(&str) -> Option<(f32, f32)>,
+    ) -> HashMap<&str, (f32, f32)> {
+        let mut values: HashMap<&str, (f32, f32)> = HashMap::new();
+        for layer in self.layers.iter().filter(|layer| layer.weight > 0.0) {
+            for (name, value) in layer.values.iter() {
+                let current = match values.get_mut(name.as_str()) {
+                    Some(current) => current,
+                    None => match default(name) {
+                        Some(start) => values.entry(name.as_str()).or_insert(start),
+                        None => continue,
+                    },
+                };
+
+                match *value {
+                    ParamValue::Scalar(x) => current.0 = layer.blend(current.0, x),
+                    ParamValue::Vec2(x, y) => {
+                        *current = (layer.blend(current.0, x), layer.blend(current.1, y))
+                    }
+                }
+            }
+        }
+
+        values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let mut mixer = ParamMixer::new();
+        mixer
+            .add_layer("manual", BlendMode::Override, 100)
+            .set("HeadX", ParamValue::Scalar(10.0));
+        mixer
+            .add_layer("breathing", BlendMode::Additive, 10)
+            .set("Breath", ParamValue::Scalar(0.5));
+        let tracking = mixer.add_layer("tracking", BlendMode::Override, 0);
+        tracking.set("HeadX", ParamValue::Scalar(4.0));
+        tracking.set("Eyes", ParamValue::Vec2(0.5, -0.5));
+        tracking.set("Missing", ParamValue::Scalar(1.0));
+
+        assert_eq!(
+            mixer.layers().map(|layer| layer.name()).collect::<Vec<_>>(),
+            vec!["tracking", "breathing", "manual"]
+        );
+
+        mixer.layer_mut("manual").unwrap().set_weight(0.5);
+        mixer.layer_mut("breathing").unwrap().set_weight(0.5);
+        let values = mixer.resolve(|name| match name {
+            "Missing" => None,
+            "Breath" => Some((1.0, 0.0)),
+            _ => Some((0.0, 2.0)),
+        });
+
+        assert_eq!(values.len(), 3);
+        /* Tracking sets 4, the manual override pulls halfway to 10, Y is untouched */
+        assert_eq!(values["HeadX"], (7.0, 2.0));
+        assert_eq!(values["Breath"], (1.25, 0.0));
+        assert_eq!(values["Eyes"], (0.5, -0.5));
+
+        mixer.layer_mut("manual").unwrap().set_weight(0.0);
+        assert_eq!(mixer.resolve(|_| Some((0.0, 0.0)))["HeadX"], (4.0, 0.0));
+    }
+}
@@ -0,0 +1,432 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+use std::ffi::CStr;
+use std::ops::Deref;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "logging")]
+use tracing::debug;
+
+use crate::{Inochi2DError, Result};
+
+use crate::ffi::{
+    inParameterGetMax, inParameterGetMin, inParameterGetName, inParameterGetUUID,
+    inParameterGetValue, inParameterIsVec2, inParameterSetValue, inPuppetGetParameters,
+    types::{InParameterPtr, InPuppetPtr},
+};
+
+/* The ranges normalized values are given in */
+const UNIT: (f32, f32) = (0.0, 1.0);
+const SIGNED: (f32, f32) = (-1.0, 1.0);
+
+/// The value of a parameter, with one component per axis.
+///
+/// Values serialize as a number or an `[x, y]` array.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ParamValue {
+    /// The value of a parameter with only an X axis.
+    Scalar(f32),
+    /// The X and Y value of a 2D parameter.
+    Vec2(f32, f32),
+}
+
+impl ParamValue {
+    pub(crate) fn new(is_vec2: bool, (x, y): (f32, f32)) -> Self {
+        if is_vec2 {
+            ParamValue::Vec2(x, y)
+        } else {
+            ParamValue::Scalar(x)
+        }
+    }
+
+    pub(crate) fn is_vec2(self) -> bool {
+        matches!(self, ParamValue::Vec2(..))
+    }
+
+    pub(crate) fn pair(self) -> (f32, f32) {
+        match self {
+            ParamValue::Scalar(x) => (x, 0.0),
+            ParamValue::Vec2(x, y) => (x, y),
+        }
+    }
+}
+
+/* Map `value` from the range `from` onto the range `to` */
+fn remap(value: f32, from: (f32, f32), to: (f32, f32)) -> f32 {
+    if from.0 == from.1 {
+        return to.0;
+    }
+
+    to.0 + (value - from.0) / (from.1 - from.0) * (to.1 - to.0)
+}
+
+/* What is known about a parameter without asking Inochi2D, read once when the puppet is loaded */
+#[derive(Debug, Clone)]
+pub(crate) struct ParamInfo {
+    handle: InParameterPtr,
+    name: String,
+    uuid: u32,
+    is_vec2: bool,
+    min: (f32, f32),
+    max: (f32, f32),
+    pub(crate) default: (f32, f32),
+}
+
+/* Read a pair of floats through an Inochi2D getter */
+fn read_pair(
+    handle: InParameterPtr,
+    getter: unsafe extern "C" fn(InParameterPtr, *mut f32, *mut f32),
+) -> (f32, f32) {
+    let (mut x, mut y) = (0.0, 0.0);
+    unsafe {
+        getter(handle, &mut x, &mut y);
+    }
+
+    (x, y)
+}
+
+impl ParamInfo {
+    /* Read every parameter of `puppet`, taking the current values as the defaults */
+    pub(crate) fn enumerate(puppet: InPuppetPtr) -> Vec<ParamInfo> {
+        let mut len: usize = 0;
+        unsafe {
+            inPuppetGetParameters(puppet, std::ptr::null_mut(), &mut len);
+        }
+        if len == 0 {
+            return Vec::new();
+        }
+
+        let mut handles: Vec<InParameterPtr> = vec![std::ptr::null_mut(); len];
+        let mut array = handles.as_mut_ptr();
+        unsafe {
+            inPuppetGetParameters(puppet, &mut array, &mut len);
+        }
+        handles.truncate(len);
+
+        #[cfg(feature = "logging")]
+        debug!("Puppet has {} parameters", handles.len());
+        handles
+            .into_iter()
+            .filter(|handle| !handle.is_null())
+            .map(|handle| {
+                let name = unsafe { inParameterGetName(handle) };
+                let name = if name.is_null() {
+                    String::new()
+                } else {
+                    unsafe { CStr::from_ptr(name) }
+                        .to_string_lossy()
+                        .into_owned()
+                };
+
+                ParamInfo {
+                    handle,
+                    name,
+                    uuid: unsafe { inParameterGetUUID(handle) },
+                    is_vec2: unsafe { inParameterIsVec2(handle) },
+                    min: read_pair(handle, inParameterGetMin),
+                    max: read_pair(handle, inParameterGetMax),
+                    default: read_pair(handle, inParameterGetValue),
+                }
+            })
+            .collect()
+    }
+
+    pub(crate) fn uuid(&self) -> u32 {
+        self.uuid
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /* Check that `value` has as many axes as the parameter */
+    fn check(&self, value: ParamValue) -> Result<(f32, f32)> {
+        if value.is_vec2() != self.is_vec2 {
+            return Err(Inochi2DError::ParamDimension {
+                name: self.name.clone(),
+                is_vec2: self.is_vec2,
+            });
+        }
+
+        Ok(value.pair())
+    }
+
+    /* Map a value in the parameter's range onto `range` */
+    fn normalize(&self, (x, y): (f32, f32), range: (f32, f32)) -> ParamValue {
+        ParamValue::new(
+            self.is_vec2,
+            (
+                remap(x, (self.min.0, self.max.0), range),
+                remap(y, (self.min.1, self.max.1), range),
+            ),
+        )
+    }
+
+    /* Map a value in `range` onto the parameter's range */
+    fn denormalize(&self, (x, y): (f32, f32), range: (f32, f32)) -> (f32, f32) {
+        (
+            remap(x, range, (self.min.0, self.max.0)),
+            remap(y, range, (self.min.1, self.max.1)),
+        )
+    }
+}
+
+/// A parameter of a loaded puppet, such as `HeadX` or `MouthOpen`.
+///
+/// Parameters are borrowed from their `Inochi2DPuppet`, see `Inochi2DPuppet::parameters`.
+/// This handle can only read the parameter, see `Inochi2DParameterMut` to change it.
+///
+pub struct Inochi2DParameter<'a> {
+    info: &'a ParamInfo,
+}
+
+impl<'a> Inochi2DParameter<'a> {
+    pub(crate) fn new(info: &'a ParamInfo) -> Self {
+        Inochi2DParameter { info }
+    }
+
+    /// Get the name of the parameter.
+    pub fn name(&self) -> &'a str {
+        &self.info.name
+    }
+
+    /// Get the UUID of the parameter, as used by bindings in the puppet file.
+    pub fn uuid(&self) -> u32 {
+        self.info.uuid
+    }
+
+    /// Check whether the parameter has both an X and a Y axis.
+    ///
+    /// The Y component of values is ignored for parameters with only an X axis.
+    ///
+    pub fn is_vec2(&self) -> bool {
+        self.info.is_vec2
+    }
+
+    /// Get the minimum value of the parameter's X and Y axis.
+    pub fn min(&self) -> (f32, f32) {
+        self.info.min
+    }
+
+    /// Get the maximum value of the parameter's X and Y axis.
+    pub fn max(&self) -> (f32, f32) {
+        self.info.max
+    }
+
+    /// Get the value the parameter rests at when nothing drives it.
+    pub fn default_value(&self) -> (f32, f32) {
+        self.info.default
+    }
+
+    /// Get the current value of the parameter.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// for param in puppet.parameters() {
+    ///     let (x, y) = param.get_value();
+    ///     println!("{}: {}, {}", param.name(), x, y);
+    /// }
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// A tuple of `f32`s with the X and Y value of the parameter.
+    ///
+    pub fn get_value(&self) -> (f32, f32) {
+        read_pair(self.info.handle, inParameterGetValue)
+    }
+
+    /// Get the current value of the parameter, with as many components as it has axes.
+    pub fn value(&self) -> ParamValue {
+        ParamValue::new(self.info.is_vec2, self.get_value())
+    }
+
+    /// Get the current value mapped from the parameter's range onto 0..1.
+    pub fn normalized(&self) -> ParamValue {
+        self.info.normalize(self.get_value(), UNIT)
+    }
+
+    /// Get the current value mapped from the parameter's range onto -1..1.
+    pub fn signed_normalized(&self) -> ParamValue {
+        self.info.normalize(self.get_value(), SIGNED)
+    }
+}
+
+/// A parameter of a loaded puppet that can be changed, see `Inochi2DPuppet::param_mut`.
+///
+/// Values set here are applied the next time the puppet is updated. The getters of
+/// `Inochi2DParameter` are available through `Deref`.
+///
+pub struct Inochi2DParameterMut<'a> {
+    param: Inochi2DParameter<'a>,
+}
+
+impl<'a> Deref for Inochi2DParameterMut<'a> {
+    type Target = Inochi2DParameter<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.param
+    }
+}
+
+impl<'a> Inochi2DParameterMut<'a> {
+    pub(crate) fn new(info: &'a ParamInfo) -> Self {
+        Inochi2DParameterMut {
+            param: Inochi2DParameter::new(info),
+        }
+    }
+
+    /// Set the current value of the parameter.
+    ///
+    /// Values outside of the parameter's range are clamped to it.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// for mut param in puppet.parameters_mut() {
+    ///     if param.name() == "HeadX" {
+    ///         param.set_value(0.5, 0.0);
+    ///     }
+    /// }
+    /// ~~~
+    ///
+    pub fn set_value(&mut self, x: f32, y: f32) {
+        let (min, max) = (self.param.info.min, self.param.info.max);
+        let x = x.clamp(min.0.min(max.0), min.0.max(max.0));
+        let y = y.clamp(min.1.min(max.1), min.1.max(max.1));
+
+        unsafe {
+            inParameterSetValue(self.param.info.handle, x, y);
+        }
+    }
+
+    /// Reset the parameter to its default value.
+    pub fn reset(&mut self) {
+        let (x, y) = self.param.info.default;
+        self.set_value(x, y);
+    }
+
+    /// Set the current value of the parameter, clamped to its range.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// if let Some(mut head) = puppet.param_mut("Head") {
+    ///     head.set(ParamValue::Vec2(0.25, -0.5))?;
+    /// }
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(())` if the value was set.
+    /// - `Inochi2DError::ParamDimension` if `value` is 1D and the parameter 2D, or the other
+    ///   way around.
+    ///
+    pub fn set(&mut self, value: ParamValue) -> Result<()> {
+        let (x, y) = self.param.info.check(value)?;
+        self.set_value(x, y);
+
+        Ok(())
+    }
+
+    /// Set the current value from 0..1, mapped onto the parameter's range.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let mut mouth = puppet.param_mut("MouthOpen").expect("no MouthOpen parameter");
+    ///
+    /// mouth.set_normalized(ParamValue::Scalar(tracking.mouth_open))?;
+    /// ~~~
+    ///
+    pub fn set_normalized(&mut self, value: ParamValue) -> Result<()> {
+        let (x, y) = self
+            .param
+            .info
+            .denormalize(self.param.info.check(value)?, UNIT);
+        self.set_value(x, y);
+
+        Ok(())
+    }
+
+    /// Set the current value from -1..1, mapped onto the parameter's range.
+    ///
+    /// -1 maps to the minimum, 0 to the middle of the range and 1 to the maximum.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let mut head = puppet.param_mut("HeadX").expect("no HeadX parameter");
+    ///
+    /// head.set_signed_normalized(ParamValue::Scalar(tracking.yaw / 45.0))?;
+    /// ~~~
+    ///
+    pub fn set_signed_normalized(&mut self, value: ParamValue) -> Result<()> {
+        let (x, y) = self
+            .param
+            .info
+            .denormalize(self.param.info.check(value)?, SIGNED);
+        self.set_value(x, y);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(is_vec2: bool) -> ParamInfo {
+        ParamInfo {
+            handle: std::ptr::null_mut(),
+            name: "Head".into(),
+            uuid: 1,
+            is_vec2,
+            min: (-30.0, 0.0),
+            max: (30.0, 10.0),
+            default: (0.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn test_normalize() {
+        let head = info(true);
+
+        assert_eq!(
+            head.denormalize((0.5, 0.25), UNIT),
+            (0.0, 2.5),
+            "0..1 maps onto min..max"
+        );
+        assert_eq!(head.denormalize((-1.0, 1.0), SIGNED), (-30.0, 10.0));
+        assert_eq!(
+            head.normalize((15.0, 5.0), SIGNED),
+            ParamValue::Vec2(0.5, 0.0)
+        );
+        assert_eq!(
+            info(false).normalize((15.0, 5.0), UNIT),
+            ParamValue::Scalar(0.75)
+        );
+    }
+
+    #[test]
+    fn test_dimension() {
+        assert!(info(true).check(ParamValue::Vec2(1.0, 2.0)).is_ok());
+        assert!(matches!(
+            info(true).check(ParamValue::Scalar(1.0)),
+            Err(Inochi2DError::ParamDimension { is_vec2: true, .. })
+        ));
+        assert!(matches!(
+            info(false).check(ParamValue::Vec2(1.0, 2.0)),
+            Err(Inochi2DError::ParamDimension { is_vec2: false, .. })
+        ));
+    }
+}
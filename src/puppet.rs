@@ -5,69 +5,753 @@
     Authors: Aki "lethalbit" Van Ness
 */
 
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
 #[cfg(feature = "logging")]
 use tracing::debug;
 
 use crate::{
+    asset::{AssetSource, FileSystemSource},
+    expression::{Expression, ExpressionSet, EXPRESSIONS_VENDOR_NAME},
     ffi::{inErrorGet, types::InPuppet},
-    Result,
+    format::{self, model::PuppetMeta, Container, LoadLimits, MigrationReport, VendorData},
+    mixer::ParamMixer,
+    param::{Inochi2DParameter, Inochi2DParameterMut, ParamInfo},
+    rights::{Enforcement, RightsPolicy, RightsViolation},
+    Inochi2DError, Result,
 };
 
 #[cfg(feature = "opengl")]
 use crate::ffi::inPuppetDraw;
 use crate::ffi::{
-    inPuppetDestroy, inPuppetGetName, inPuppetLoad, inPuppetLoadEx, inPuppetLoadFromMemory,
-    inPuppetUpdate, types::InPuppetPtr,
+    inPuppetDestroy, inPuppetGetName, inPuppetLoad, inPuppetLoadFromMemory, inPuppetUpdate,
+    types::InPuppetPtr,
 };
 
+/// Where a puppet was loaded from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PuppetSource {
+    /// A puppet loaded from a file on disk.
+    Path(PathBuf),
+    /// A puppet loaded from memory, with an optional name.
+    Memory(Option<String>),
+}
+
+impl fmt::Display for PuppetSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PuppetSource::Path(path) => write!(f, "{}", path.display()),
+            PuppetSource::Memory(Some(name)) => write!(f, "{} (in-memory)", name),
+            PuppetSource::Memory(None) => write!(f, "<in-memory-puppet>"),
+        }
+    }
+}
+
+/* Checks and fixups applied to puppet data before Inochi2D loads it */
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LoadOptions {
+    pub(crate) rights_policy: Option<RightsPolicy>,
+    pub(crate) migrate: bool,
+    pub(crate) limits: Option<LoadLimits>,
+}
+
+/* Puppet data that passed the `LoadOptions` checks, with everything read from it that does
+not need Inochi2D. Preparing is pure Rust, so background loaders do it on their worker */
+pub(crate) struct PreparedPuppet<'a> {
+    data: Cow<'a, [u8]>,
+    source: PuppetSource,
+    meta: Option<PuppetMeta>,
+    violations: Vec<RightsViolation>,
+    vendor: Vec<VendorData>,
+    expressions: ExpressionSet,
+    migration: Option<MigrationReport>,
+    defaults: Vec<(u32, [f32; 2])>,
+}
+
+impl<'a> PreparedPuppet<'a> {
+    /* Check limits, migrate and check usage rights as `options` asks */
+    pub(crate) fn new(
+        data: Cow<'a, [u8]>,
+        source: PuppetSource,
+        options: &LoadOptions,
+    ) -> Result<Self> {
+        if let Some(limits) = &options.limits {
+            format::check_limits(&data, limits)?;
+        }
+
+        let (data, migration) = if options.migrate {
+            let mut container = Container::parse(&data)?;
+            let report = container.migrate()?;
+
+            #[cfg(feature = "logging")]
+            debug!("Migrated {} with {} changes", source, report.changes.len());
+            if report.is_empty() {
+                (data, Some(report))
+            } else {
+                (Cow::Owned(container.to_bytes()?), Some(report))
+            }
+        } else {
+            (data, None)
+        };
+
+        let policy = options.rights_policy.as_ref();
+        let (meta, violations) = match policy {
+            Some(policy) => policy.check_bytes(&data),
+            None => (format::read_meta_from_bytes(&data).ok(), Vec::new()),
+        };
+
+        if let Some(policy) = policy {
+            if !violations.is_empty() && policy.enforcement == Enforcement::Refuse {
+                return Err(Inochi2DError::Rights { source, violations });
+            }
+        }
+
+        let vendor = format::read_vendor_data_from_bytes(&data).unwrap_or_default();
+        let expressions = vendor
+            .iter()
+            .find(|section| section.name == EXPRESSIONS_VENDOR_NAME)
+            .and_then(|section| std::str::from_utf8(&section.payload).ok())
+            .and_then(|json| ExpressionSet::from_json(json).ok())
+            .unwrap_or_default();
+        let defaults = format::read_param_defaults_from_bytes(&data).unwrap_or_default();
+
+        Ok(PreparedPuppet {
+            data,
+            source,
+            meta,
+            violations,
+            vendor,
+            expressions,
+            migration,
+            defaults,
+        })
+    }
+}
+
 pub struct Inochi2DPuppet {
     handle: InPuppetPtr,
-    pub name: String,
+    name: String,
+    source: PuppetSource,
+    meta: Option<PuppetMeta>,
+    violations: Vec<RightsViolation>,
+    vendor: Vec<VendorData>,
+    migration: Option<MigrationReport>,
+    params: Vec<ParamInfo>,
+    param_names: HashMap<String, usize>,
+    mixer: ParamMixer,
+    mixer_suspended: bool,
+    expressions: ExpressionSet,
 }
 
 impl Inochi2DPuppet {
-    pub fn from_raw_handle(handle: *mut InPuppet, name: String) -> Result<Self> {
+    pub fn from_raw_handle(handle: *mut InPuppet, source: PuppetSource) -> Result<Self> {
         if handle.is_null() {
             let error_information = unsafe { inErrorGet() };
 
             if error_information.is_null() {
-                Err("Unknown error (are C bindings valid?)".into())
+                Err(Inochi2DError::NullHandle(source))
             } else {
-                let error_res = unsafe { String::try_from(*error_information) };
+                let message = unsafe { String::try_from(*error_information) }?;
 
-                Err(error_res.unwrap_or_else(|_| "Unknown error (UTF-8 decoding failed!)".into()))
+                Err(Inochi2DError::Load { source, message })
             }
         } else {
-            Ok(Inochi2DPuppet { handle, name })
+            let mut puppet = Inochi2DPuppet {
+                handle,
+                name: String::new(),
+                source,
+                meta: None,
+                violations: Vec::new(),
+                vendor: Vec::new(),
+                migration: None,
+                params: ParamInfo::enumerate(handle),
+                param_names: HashMap::new(),
+                mixer: ParamMixer::new(),
+                mixer_suspended: false,
+                expressions: ExpressionSet::new(),
+            };
+            /* Puppets can repeat names, the first parameter with a name wins like in Inochi2D */
+            for (index, param) in puppet.params.iter().enumerate() {
+                puppet
+                    .param_names
+                    .entry(param.name().to_string())
+                    .or_insert(index);
+            }
+            puppet.name = puppet
+                .read_name()
+                .unwrap_or_else(|| puppet.source.to_string());
+
+            Ok(puppet)
+        }
+    }
+
+    /* Read the name from the puppet metadata, if it has one */
+    fn read_name(&self) -> Option<String> {
+        let mut name: *const u8 = std::ptr::null();
+        let mut len: usize = 0;
+
+        unsafe {
+            inPuppetGetName(self.handle, &mut name, &mut len);
+        }
+
+        if name.is_null() || len == 0 {
+            None
+        } else {
+            let slice = unsafe { std::slice::from_raw_parts(name, len) };
+            Some(String::from_utf8_lossy(slice).into_owned())
+        }
+    }
+
+    /// Get the name of the puppet.
+    ///
+    /// This is the name from the puppet's metadata, or a description of where the
+    /// puppet was loaded from if the metadata does not name it.
+    ///
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get where the puppet was loaded from.
+    pub fn source(&self) -> &PuppetSource {
+        &self.source
+    }
+
+    /// Get the puppet's metadata.
+    ///
+    /// To read the metadata of a puppet file without loading it use `format::read_meta`.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let puppet = Inochi2DPuppet::new("./puppets/Ada.inx")?;
+    ///
+    /// if let Some(meta) = puppet.meta() {
+    ///     println!("{:?} by {:?}", meta.name, meta.artist);
+    /// }
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// The metadata, or `None` if the puppet data could not be read by Rust.
+    ///
+    pub fn meta(&self) -> Option<&PuppetMeta> {
+        self.meta.as_ref()
+    }
+
+    /// Get the conflicts between the puppet's usage rights and the `RightsPolicy` it was
+    /// loaded under.
+    ///
+    /// Puppets with violations are only loaded under `Enforcement::Flag`, and are never drawn.
+    ///
+    pub fn rights_violations(&self) -> &[RightsViolation] {
+        &self.violations
+    }
+
+    /// Get what was changed to upgrade the puppet to the current schema.
+    ///
+    /// # Returns
+    ///
+    /// The migration report, or `None` if the puppet was loaded without migrating.
+    ///
+    pub fn migration(&self) -> Option<&MigrationReport> {
+        self.migration.as_ref()
+    }
+
+    /// Get the puppet's parameters, in the order Inochi2D lists them.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let puppet = Inochi2DPuppet::new("./puppets/Ada.inx")?;
+    ///
+    /// for param in puppet.parameters() {
+    ///     println!("{} {:?}..{:?}", param.name(), param.min(), param.max());
+    /// }
+    /// ~~~
+    ///
+    pub fn parameters(&self) -> Vec<Inochi2DParameter<'_>> {
+        self.params.iter().map(Inochi2DParameter::new).collect()
+    }
+
+    /// Get the puppet's parameters to change their values, see `parameters`.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// for mut param in puppet.parameters_mut() {
+    ///     param.reset();
+    /// }
+    /// ~~~
+    ///
+    pub fn parameters_mut(&mut self) -> Vec<Inochi2DParameterMut<'_>> {
+        self.params.iter().map(Inochi2DParameterMut::new).collect()
+    }
+
+    /// Look up a parameter by name.
+    ///
+    /// Names are looked up in a map built when the puppet is loaded, so this is cheap
+    /// enough to call for every parameter on every frame.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let puppet = Inochi2DPuppet::new("./puppets/Ada.inx")?;
+    ///
+    /// if let Some(head) = puppet.param("HeadX") {
+    ///     println!("HeadX is at {:?}", head.signed_normalized());
+    /// }
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// The parameter, or `None` if the puppet has no parameter called `name`.
+    ///
+    pub fn param(&self, name: &str) -> Option<Inochi2DParameter<'_>> {
+        self.param_names
+            .get(name)
+            .map(|&index| Inochi2DParameter::new(&self.params[index]))
+    }
+
+    /// Look up a parameter by name to change its value, see `param`.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let mut puppet = Inochi2DPuppet::new("./puppets/Ada.inx")?;
+    ///
+    /// if let Some(mut head) = puppet.param_mut("HeadX") {
+    ///     head.set_signed_normalized(ParamValue::Scalar(0.5))?;
+    /// }
+    /// puppet.update();
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// The parameter, or `None` if the puppet has no parameter called `name`.
+    ///
+    pub fn param_mut(&mut self, name: &str) -> Option<Inochi2DParameterMut<'_>> {
+        self.param_names
+            .get(name)
+            .map(|&index| Inochi2DParameterMut::new(&self.params[index]))
+    }
+
+    /// Get the parameter mixer, which is resolved on every `update`.
+    pub fn mixer(&self) -> &ParamMixer {
+        &self.mixer
+    }
+
+    /// Get the parameter mixer to add layers or change their values, see `ParamMixer`.
+    pub fn mixer_mut(&mut self) -> &mut ParamMixer {
+        &mut self.mixer
+    }
+
+    /// Check whether `update` skips the mixer, see `set_mixer_suspended`.
+    pub fn mixer_suspended(&self) -> bool {
+        self.mixer_suspended
+    }
+
+    /// Stop or resume resolving the mixer on `update`.
+    ///
+    /// While suspended, parameters keep the values set on them directly and expressions or
+    /// other layers have no effect. Layers keep their values and weights, so resuming picks
+    /// up where they were. `recording::Player` suspends the mixer while it replays values.
+    ///
+    pub fn set_mixer_suspended(&mut self, suspended: bool) {
+        self.mixer_suspended = suspended;
+    }
+
+    /// Get the puppet's expressions.
+    ///
+    /// Expressions embedded in the puppet's vendor data are loaded with the puppet.
+    ///
+    pub fn expressions(&self) -> &ExpressionSet {
+        &self.expressions
+    }
+
+    /// Get the puppet's expressions to add or remove some.
+    pub fn expressions_mut(&mut self) -> &mut ExpressionSet {
+        &mut self.expressions
+    }
+
+    /// Capture the current values of the parameters called `params` into an expression.
+    ///
+    /// Names the puppet has no parameter for are skipped.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let shocked = puppet.capture_expression("shocked", &["MouthOpen", "EyeWide", "BrowY"]);
+    ///
+    /// puppet.expressions_mut().insert(shocked);
+    /// ~~~
+    ///
+    pub fn capture_expression(&self, name: &str, params: &[&str]) -> Expression {
+        Expression {
+            name: name.to_string(),
+            values: params
+                .iter()
+                .filter_map(|&param| Some((param.to_string(), self.param(param)?.value())))
+                .collect(),
+        }
+    }
+
+    /// Embed the puppet's expressions in its vendor data, see `EXPRESSIONS_VENDOR_NAME`.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// puppet.embed_expressions()?;
+    /// puppet.save_vendor_data("./puppets/Ada.inx")?;
+    /// ~~~
+    ///
+    pub fn embed_expressions(&mut self) -> Result<()> {
+        let json = self
+            .expressions
+            .to_json()
+            .map_err(format::FormatError::Json)?;
+        self.set_vendor_data(EXPRESSIONS_VENDOR_NAME, json.into_bytes());
+
+        Ok(())
+    }
+
+    /* Set every parameter a mixer layer has a value for */
+    fn apply_mixer(&mut self) {
+        if self.mixer_suspended || self.mixer.is_empty() {
+            return;
+        }
+
+        let values = self.mixer.resolve(|name| {
+            self.param_names
+                .get(name)
+                .map(|&index| self.params[index].default)
+        });
+        for (name, (x, y)) in values {
+            if let Some(&index) = self.param_names.get(name) {
+                Inochi2DParameterMut::new(&self.params[index]).set_value(x, y);
+            }
+        }
+    }
+
+    /* Take over the parameter values, mixer layers and expressions of `old`, which this puppet replaces */
+    pub(crate) fn carry_over(&mut self, old: &mut Inochi2DPuppet) {
+        for param in old.parameters() {
+            let target = match self.params.iter().find(|p| p.uuid() == param.uuid()) {
+                Some(target) => Some(target),
+                None => self
+                    .param_names
+                    .get(param.name())
+                    .map(|&index| &self.params[index]),
+            };
+
+            if let Some(target) = target {
+                let (x, y) = param.get_value();
+                Inochi2DParameterMut::new(target).set_value(x, y);
+            }
+        }
+
+        self.mixer = std::mem::take(&mut old.mixer);
+        self.mixer_suspended = old.mixer_suspended;
+        self.expressions = std::mem::take(&mut old.expressions);
+    }
+
+    /// Get the names of the puppet's vendor data sections, in file order.
+    pub fn vendor_names(&self) -> impl Iterator<Item = &str> {
+        self.vendor.iter().map(|section| section.name.as_str())
+    }
+
+    /// Get the payload of the vendor data section called `name`.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let puppet = Inochi2DPuppet::new("./puppets/Ada.inx")?;
+    ///
+    /// if let Some(config) = puppet.vendor_data("com.example.tracking") {
+    ///     println!("{} bytes of tracking config", config.len());
+    /// }
+    /// ~~~
+    ///
+    pub fn vendor_data(&self, name: &str) -> Option<&[u8]> {
+        self.vendor
+            .iter()
+            .find(|section| section.name == name)
+            .map(|section| section.payload.as_slice())
+    }
+
+    /// Set the payload of the vendor data section called `name`, adding the section if needed.
+    ///
+    /// This only changes the loaded puppet, use `save_vendor_data` to write the sections
+    /// back to a puppet file.
+    ///
+    /// # Returns
+    ///
+    /// The previous payload of the section, if it existed.
+    ///
+    pub fn set_vendor_data(
+        &mut self,
+        name: impl Into<String>,
+        payload: impl Into<Vec<u8>>,
+    ) -> Option<Vec<u8>> {
+        format::set_vendor_data(&mut self.vendor, name.into(), payload.into())
+    }
+
+    /// Remove the vendor data section called `name`, returning its payload.
+    pub fn remove_vendor_data(&mut self, name: &str) -> Option<Vec<u8>> {
+        format::remove_vendor_data(&mut self.vendor, name)
+    }
+
+    /// Replace the vendor data sections of the puppet file at `path` with this puppet's.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let mut puppet = Inochi2DPuppet::new("./puppets/Ada.inx")?;
+    ///
+    /// puppet.set_vendor_data("com.example.hotkeys", br#"{"F1":"wave"}"#.to_vec());
+    /// puppet.save_vendor_data("./puppets/Ada.inx")?;
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// - If the file was updated `()`.
+    /// - Otherwise an `Inochi2DError` describing the error.
+    ///
+    pub fn save_vendor_data(&self, path: impl AsRef<Path>) -> Result<()> {
+        Ok(format::write_vendor_data(path, self.vendor.clone())?)
+    }
+
+    /* Load `buffer` through Inochi2D and fill in the metadata from the same data */
+    pub(crate) fn from_source_bytes(buffer: &[u8], source: PuppetSource) -> Result<Self> {
+        Self::from_checked_bytes(buffer, source, &LoadOptions::default())
+    }
+
+    /* As `from_source_bytes`, checking limits, migrating and checking usage rights as `options` asks */
+    pub(crate) fn from_checked_bytes(
+        buffer: &[u8],
+        source: PuppetSource,
+        options: &LoadOptions,
+    ) -> Result<Self> {
+        Self::from_prepared(PreparedPuppet::new(buffer.into(), source, options)?)
+    }
+
+    /* Hand prepared data to Inochi2D, the only step that needs the context's thread */
+    pub(crate) fn from_prepared(prepared: PreparedPuppet<'_>) -> Result<Self> {
+        let buffer = prepared.data.as_ref();
+        let hndl = unsafe { inPuppetLoadFromMemory(buffer.as_ptr(), buffer.len()) };
+
+        let mut puppet = Self::from_raw_handle(hndl, prepared.source)?;
+        puppet.meta = prepared.meta;
+        puppet.violations = prepared.violations;
+        puppet.vendor = prepared.vendor;
+        puppet.expressions = prepared.expressions;
+        puppet.migration = prepared.migration;
+        for (uuid, default) in prepared.defaults {
+            if let Some(param) = puppet.params.iter_mut().find(|p| p.uuid() == uuid) {
+                param.default = (default[0], default[1]);
+            }
         }
+
+        Ok(puppet)
     }
 
+    /// Load a puppet from `size` bytes of INP/INX data at `buffer`.
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must either be null or point to `size` bytes that are valid for reads and
+    /// not mutated for the duration of the call, see `std::slice::from_raw_parts`.
+    ///
+    /// # Returns
+    ///
+    /// - If loading was successful a new `Inochi2DPuppet`.
+    /// - If `buffer` is null `Inochi2DError::NullBuffer`.
+    /// - If any other error occurred an `Inochi2DError` describing the error will be returned.
+    ///
     pub unsafe fn from_raw_parts(
         buffer: *const u8,
         size: usize,
         name: Option<String>,
     ) -> Result<Self> {
+        if buffer.is_null() {
+            return Err(Inochi2DError::NullBuffer);
+        }
+
+        let buffer = unsafe { std::slice::from_raw_parts(buffer, size) };
+
+        Self::from_bytes(buffer, name)
+    }
+
+    /// Load a puppet from an in-memory INP/INX buffer.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let data = include_bytes!("../examples/models/Aka.inx");
+    /// let puppet = Inochi2DPuppet::from_bytes(data, Some("Aka".into()))?;
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// - If loading was successful a new `Inochi2DPuppet`.
+    /// - If an error occurred an `Inochi2DError` describing the error will be returned.
+    ///
+    pub fn from_bytes(buffer: &[u8], name: Option<String>) -> Result<Self> {
         #[cfg(feature = "logging")]
-        debug!("Constructing puppet from {} bytes", size);
-        let hndl = unsafe { inPuppetLoadFromMemory(buffer, size) };
+        debug!("Constructing puppet from {} bytes", buffer.len());
+
+        Self::from_source_bytes(buffer, PuppetSource::Memory(name))
+    }
+
+    /// Load a puppet from an in-memory INP/INX buffer, upgrading older puppets to the
+    /// current schema first, see `format::migrate_json`.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let data = include_bytes!("../examples/models/Aka.inx");
+    /// let puppet = Inochi2DPuppet::from_bytes_migrated(data, Some("Aka".into()))?;
+    ///
+    /// if let Some(report) = puppet.migration() {
+    ///     println!("Loaded a {} schema puppet", report.from);
+    /// }
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// - If loading was successful a new `Inochi2DPuppet`.
+    /// - If an error occurred an `Inochi2DError` describing the error will be returned.
+    ///
+    pub fn from_bytes_migrated(buffer: &[u8], name: Option<String>) -> Result<Self> {
+        let options = LoadOptions {
+            migrate: true,
+            ..LoadOptions::default()
+        };
+
+        Self::from_checked_bytes(buffer, PuppetSource::Memory(name), &options)
+    }
+
+    /// Load a puppet from an untrusted in-memory INP/INX buffer, refusing it before it
+    /// reaches Inochi2D if it exceeds `limits`, see `format::check_limits`.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let puppet = Inochi2DPuppet::from_bytes_limited(&upload, None, &LoadLimits::default())?;
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// - If loading was successful a new `Inochi2DPuppet`.
+    /// - If an error occurred an `Inochi2DError` describing the error will be returned.
+    ///
+    pub fn from_bytes_limited(
+        buffer: &[u8],
+        name: Option<String>,
+        limits: &LoadLimits,
+    ) -> Result<Self> {
+        let options = LoadOptions {
+            limits: Some(limits.clone()),
+            ..LoadOptions::default()
+        };
+
+        Self::from_checked_bytes(buffer, PuppetSource::Memory(name), &options)
+    }
 
-        Self::from_raw_handle(hndl, name.unwrap_or(String::from("<in-memory-puppet>")))
+    /// Load a puppet from a parsed INP/INX container.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let mut container = Container::open("./puppets/Ada.inx")?;
+    /// container.vendor.clear();
+    ///
+    /// let puppet = Inochi2DPuppet::from_container(&container, Some("Ada".into()))?;
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// - If loading was successful a new `Inochi2DPuppet`.
+    /// - If an error occurred an `Inochi2DError` describing the error will be returned.
+    ///
+    pub fn from_container(container: &Container, name: Option<String>) -> Result<Self> {
+        Self::from_bytes(&container.to_bytes()?, name)
     }
 
-    pub fn new(puppet: PathBuf) -> Result<Self> {
-        let puppet_path = String::from(puppet.to_str().expect("Unable to get puppet path"));
+    /// Load a puppet by reading an INP/INX file from `reader` until EOF.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let file = std::fs::File::open("./puppets/Ada.inx")?;
+    /// let puppet = Inochi2DPuppet::from_reader(file, Some("Ada".into()))?;
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// - If loading was successful a new `Inochi2DPuppet`.
+    /// - If an error occurred an `Inochi2DError` describing the error will be returned.
+    ///
+    pub fn from_reader<R: Read>(mut reader: R, name: Option<String>) -> Result<Self> {
+        let mut buffer = Vec::new();
+        reader.read_to_end(&mut buffer)?;
+
+        Self::from_bytes(&buffer, name)
+    }
+
+    /// Load the puppet `id` from an asset source.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let source = FileSystemSource::new("./puppets");
+    /// let puppet = Inochi2DPuppet::from_asset(&source, "Ada.inx")?;
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// - If loading was successful a new `Inochi2DPuppet`.
+    /// - If `id` could not be found `Inochi2DError::InvalidPath`.
+    /// - If any other error occurred an `Inochi2DError` describing the error will be returned.
+    ///
+    pub fn from_asset(source: &dyn AssetSource, id: impl AsRef<Path>) -> Result<Self> {
+        let id = id.as_ref();
         #[cfg(feature = "logging")]
-        debug!("Constructing puppet from file {}", puppet_path);
-        let hndl = unsafe { inPuppetLoadEx(puppet_path.as_ptr(), puppet_path.len()) };
+        debug!("Constructing puppet from asset {}", id.display());
+        let buffer = source
+            .load(id)
+            .map_err(|err| Inochi2DError::from_asset_io(id, err))?;
 
-        Self::from_raw_handle(hndl, puppet_path)
+        Self::from_source_bytes(&buffer, PuppetSource::Path(id.to_path_buf()))
+    }
+
+    /// Load a puppet from a file on disk.
+    ///
+    /// The file is read by Rust, so paths that are not valid UTF-8 are supported.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let puppet = Inochi2DPuppet::new("./puppets/Ada.inx")?;
+    /// ~~~
+    ///
+    /// # Returns
+    ///
+    /// - If loading was successful a new `Inochi2DPuppet`.
+    /// - If the file does not exist `Inochi2DError::InvalidPath`.
+    /// - If any other error occurred an `Inochi2DError` describing the error will be returned.
+    ///
+    pub fn new(puppet: impl AsRef<Path>) -> Result<Self> {
+        Self::from_asset(&FileSystemSource::default(), puppet)
     }
 
     pub fn update(&mut self) {
         #[cfg(feature = "logging")]
         debug!("Updating puppet {}", self.name);
+        self.apply_mixer();
 
         unsafe {
             inPuppetUpdate(self.handle);
@@ -76,6 +760,12 @@ impl Inochi2DPuppet {
 
     #[cfg(feature = "opengl")]
     pub fn draw(&mut self) {
+        if !self.violations.is_empty() {
+            #[cfg(feature = "logging")]
+            debug!("Not drawing puppet {}, usage rights violated", self.name);
+            return;
+        }
+
         #[cfg(feature = "logging")]
         debug!("Drawing puppet {}", self.name);
 
@@ -95,3 +785,30 @@ impl Drop for Inochi2DPuppet {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::puppet::Inochi2DPuppet;
+    use crate::Inochi2DError;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_path() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        use std::path::Path;
+
+        let path = Path::new(OsStr::from_bytes(b"/nonexistent/Mod\xe8le.inx"));
+
+        match Inochi2DPuppet::new(path) {
+            Err(Inochi2DError::InvalidPath(p)) => assert_eq!(p, path),
+            _ => panic!("expected InvalidPath"),
+        }
+    }
+    #[test]
+    fn test_null_buffer() {
+        let res = unsafe { Inochi2DPuppet::from_raw_parts(std::ptr::null(), 0, None) };
+
+        assert!(matches!(res, Err(Inochi2DError::NullBuffer)));
+    }
+}
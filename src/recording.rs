@@ -0,0 +1,723 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::{self, Utf8Error};
+
+#[cfg(feature = "logging")]
+use tracing::debug;
+
+use crate::param::ParamValue;
+use crate::puppet::Inochi2DPuppet;
+
+/// Magic bytes at the start of a recording file.
+pub const RECORDING_MAGIC: &[u8; 8] = b"INPREC\0\0";
+
+/// Version of the recording format written by this crate.
+pub const RECORDING_VERSION: u32 = 1;
+
+/// Errors produced while reading or writing a recording.
+#[derive(Debug)]
+pub enum RecordingError {
+    /// The data does not start with `RECORDING_MAGIC`.
+    InvalidMagic,
+    /// The recording was written by a newer version of the format.
+    UnsupportedVersion(u32),
+    /// The data ended while `needed` more bytes were expected at `offset`.
+    UnexpectedEof { offset: usize, needed: usize },
+    /// A frame refers to parameter `index`, which the recording does not list.
+    UnknownParam { index: u16 },
+    /// The timestamp of `frame` is not finite, negative or before the frame it follows.
+    InvalidTimestamp { frame: usize, time: f64 },
+    /// `what` is `len` bytes or entries long, more than the format can store.
+    TooLarge { what: &'static str, len: usize },
+    /// A parameter name was not valid UTF-8.
+    Utf8(Utf8Error),
+    /// Reading or writing the recording failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordingError::InvalidMagic => write!(f, "Not a parameter recording (bad magic)"),
+            RecordingError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported recording version {}", version)
+            }
+            RecordingError::UnexpectedEof { offset, needed } => write!(
+                f,
+                "Unexpected end of recording at offset {} ({} more bytes needed)",
+                offset, needed
+            ),
+            RecordingError::UnknownParam { index } => {
+                write!(f, "Recording refers to unknown parameter {}", index)
+            }
+            RecordingError::InvalidTimestamp { frame, time } => {
+                write!(f, "Frame {} has invalid timestamp {}", frame, time)
+            }
+            RecordingError::TooLarge { what, len } => {
+                write!(f, "The {} is too large to store ({})", what, len)
+            }
+            RecordingError::Utf8(err) => write!(f, "Invalid UTF-8 in parameter name: {}", err),
+            RecordingError::Io(err) => write!(f, "Unable to read or write recording: {}", err),
+        }
+    }
+}
+
+impl Error for RecordingError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RecordingError::Utf8(err) => Some(err),
+            RecordingError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RecordingError {
+    fn from(err: io::Error) -> Self {
+        RecordingError::Io(err)
+    }
+}
+
+/* A recorded parameter */
+#[derive(Debug, Clone, PartialEq)]
+struct Track {
+    name: String,
+    is_vec2: bool,
+}
+
+/* The parameters that changed at `time` seconds into the recording */
+#[derive(Debug, Clone, PartialEq)]
+struct Frame {
+    time: f64,
+    values: Vec<(u16, f32, f32)>,
+}
+
+/// Timestamped parameter values of a puppet, see `Recorder` and `Player`.
+///
+/// Recordings are stored as big-endian binary, like puppet containers: `RECORDING_MAGIC`
+/// and the version, the recorded parameters, then each frame as its timestamp and the
+/// parameters that changed since the frame before.
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recording {
+    tracks: Vec<Track>,
+    frames: Vec<Frame>,
+}
+
+/* A bounds checked cursor over recording data */
+struct Cursor<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], RecordingError> {
+        let remaining = self.data.len() - self.offset;
+        if remaining < len {
+            return Err(RecordingError::UnexpectedEof {
+                offset: self.offset,
+                needed: len - remaining,
+            });
+        }
+
+        let slice = &self.data[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], RecordingError> {
+        let mut bytes = [0u8; N];
+        bytes.copy_from_slice(self.take(N)?);
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, RecordingError> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, RecordingError> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, RecordingError> {
+        Ok(f32::from_be_bytes(self.array()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, RecordingError> {
+        Ok(f64::from_be_bytes(self.array()?))
+    }
+}
+
+impl Recording {
+    /// Get the length of the recording in seconds.
+    pub fn duration(&self) -> f64 {
+        self.frames.last().map_or(0.0, |frame| frame.time)
+    }
+
+    /// Get the number of recorded frames.
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Get the names of the recorded parameters.
+    pub fn param_names(&self) -> impl Iterator<Item = &str> {
+        self.tracks.iter().map(|track| track.name.as_str())
+    }
+
+    /// Parse a recording from memory.
+    pub fn parse(data: &[u8]) -> Result<Self, RecordingError> {
+        if !data.starts_with(RECORDING_MAGIC) {
+            return Err(RecordingError::InvalidMagic);
+        }
+        let mut cursor = Cursor {
+            data,
+            offset: RECORDING_MAGIC.len(),
+        };
+
+        let version = cursor.u32()?;
+        if version > RECORDING_VERSION {
+            return Err(RecordingError::UnsupportedVersion(version));
+        }
+
+        let mut tracks = Vec::new();
+        for _ in 0..cursor.u16()? {
+            let len = cursor.u16()? as usize;
+            let name = str::from_utf8(cursor.take(len)?).map_err(RecordingError::Utf8)?;
+            let is_vec2 = cursor.take(1)?[0] != 0;
+
+            tracks.push(Track {
+                name: name.to_string(),
+                is_vec2,
+            });
+        }
+
+        let mut frames: Vec<Frame> = Vec::new();
+        for frame in 0..cursor.u32()? as usize {
+            let time = cursor.f64()?;
+            let previous = frames.last().map_or(0.0, |frame| frame.time);
+            if !time.is_finite() || time < previous {
+                return Err(RecordingError::InvalidTimestamp { frame, time });
+            }
+
+            let mut values = Vec::new();
+            for _ in 0..cursor.u16()? {
+                let index = cursor.u16()?;
+                let track = tracks
+                    .get(index as usize)
+                    .ok_or(RecordingError::UnknownParam { index })?;
+                let x = cursor.f32()?;
+                let y = if track.is_vec2 { cursor.f32()? } else { 0.0 };
+
+                values.push((index, x, y));
+            }
+
+            frames.push(Frame { time, values });
+        }
+
+        Ok(Recording { tracks, frames })
+    }
+
+    /// Open the recording file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+        Recording::parse(&fs::read(path)?)
+    }
+
+    /// Write the recording to `writer`.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(())` if the recording was written.
+    /// - `RecordingError::TooLarge` if a parameter name is over 64 KiB, or there are too many
+    ///   parameters or frames to store, nothing is written then.
+    /// - Otherwise `RecordingError::Io` describing the error.
+    ///
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), RecordingError> {
+        let too_large = |what, len| RecordingError::TooLarge { what, len };
+        let u16_len = |what, len: usize| u16::try_from(len).map_err(|_| too_large(what, len));
+        let tracks = u16_len("parameter table", self.tracks.len())?;
+        let frames = u32::try_from(self.frames.len())
+            .map_err(|_| too_large("frame table", self.frames.len()))?;
+        for track in self.tracks.iter() {
+            u16_len("parameter name", track.name.len())?;
+        }
+        for frame in self.frames.iter() {
+            u16_len("frame", frame.values.len())?;
+        }
+
+        writer.write_all(RECORDING_MAGIC)?;
+        writer.write_all(&RECORDING_VERSION.to_be_bytes())?;
+
+        writer.write_all(&tracks.to_be_bytes())?;
+        for track in self.tracks.iter() {
+            writer.write_all(&(track.name.len() as u16).to_be_bytes())?;
+            writer.write_all(track.name.as_bytes())?;
+            writer.write_all(&[track.is_vec2 as u8])?;
+        }
+
+        writer.write_all(&frames.to_be_bytes())?;
+        for frame in self.frames.iter() {
+            writer.write_all(&frame.time.to_be_bytes())?;
+            writer.write_all(&(frame.values.len() as u16).to_be_bytes())?;
+            for &(index, x, y) in frame.values.iter() {
+                writer.write_all(&index.to_be_bytes())?;
+                writer.write_all(&x.to_be_bytes())?;
+                if self.tracks[index as usize].is_vec2 {
+                    writer.write_all(&y.to_be_bytes())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Serialize the recording into a new buffer, see `write`.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, RecordingError> {
+        let mut data = Vec::new();
+        self.write(&mut data)?;
+
+        Ok(data)
+    }
+
+    /// Write the recording to the file at `path`, see `write`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), RecordingError> {
+        let data = self.to_bytes()?;
+
+        Ok(fs::write(path, data)?)
+    }
+}
+
+/// Records the parameter values of a puppet over time.
+///
+/// # Example
+///
+/// ~~~no_run
+/// let mut recorder = Recorder::new(ctx.timing_function());
+///
+/// while recording {
+///     let puppet = &mut ctx.puppets[0];
+///     puppet.update();
+///     recorder.capture(puppet);
+/// }
+///
+/// recorder.finish().save("./bug-report.inprec")?;
+/// ~~~
+///
+pub struct Recorder {
+    timing: extern "C" fn() -> f64,
+    start: Option<f64>,
+    indices: HashMap<String, u16>,
+    last: Vec<(f32, f32)>,
+    recording: Recording,
+}
+
+impl Recorder {
+    /// Create a recorder that reads the time from `timing`, usually
+    /// `Inochi2D::timing_function`.
+    pub fn new(timing: extern "C" fn() -> f64) -> Self {
+        Recorder {
+            timing,
+            start: None,
+            indices: HashMap::new(),
+            last: Vec::new(),
+            recording: Recording::default(),
+        }
+    }
+
+    /// Record the current value of every parameter of `puppet`.
+    ///
+    /// Timestamps are relative to the first capture, and only parameters that changed since
+    /// the last capture are stored.
+    ///
+    pub fn capture(&mut self, puppet: &Inochi2DPuppet) {
+        let now = (self.timing)();
+        let values = puppet
+            .parameters()
+            .into_iter()
+            .map(|param| (param.name().to_string(), param.value()))
+            .collect::<Vec<_>>();
+
+        self.capture_at(now, values);
+    }
+
+    fn capture_at(&mut self, now: f64, values: Vec<(String, ParamValue)>) {
+        let start = *self.start.get_or_insert(now);
+        /* Keep timestamps finite and in order even if the clock misbehaves */
+        let previous = self.recording.frames.last().map_or(0.0, |frame| frame.time);
+        let time = now - start;
+        let mut frame = Frame {
+            time: if time.is_finite() {
+                time.max(previous)
+            } else {
+                previous
+            },
+            values: Vec::new(),
+        };
+
+        for (name, value) in values {
+            let index = match self.indices.get(&name) {
+                Some(&index) => index,
+                None if self.recording.tracks.len() < u16::MAX as usize
+                    && name.len() <= u16::MAX as usize =>
+                {
+                    let index = self.recording.tracks.len() as u16;
+                    self.recording.tracks.push(Track {
+                        name: name.clone(),
+                        is_vec2: matches!(value, ParamValue::Vec2(..)),
+                    });
+                    self.indices.insert(name, index);
+                    self.last.push((f32::NAN, f32::NAN));
+                    index
+                }
+                None => continue,
+            };
+
+            let (x, y) = value.pair();
+            let last = &mut self.last[index as usize];
+            if last.0.to_bits() != x.to_bits() || last.1.to_bits() != y.to_bits() {
+                *last = (x, y);
+                frame.values.push((index, x, y));
+            }
+        }
+
+        if frame.values.len() <= u16::MAX as usize {
+            self.recording.frames.push(frame);
+        }
+    }
+
+    /// Stop recording.
+    pub fn finish(self) -> Recording {
+        #[cfg(feature = "logging")]
+        debug!(
+            "Recorded {} frames over {}s",
+            self.recording.frames.len(),
+            self.recording.duration()
+        );
+
+        self.recording
+    }
+}
+
+/// Replays a `Recording` onto a puppet, with seek, loop and speed controls.
+///
+/// Values are set on the puppet's parameters as recorded, without interpolation, so replays
+/// are deterministic. The mixer of a puppet being replayed onto is suspended so expressions
+/// and other layers don't change the recorded values, `detach` resumes it.
+///
+/// # Example
+///
+/// ~~~no_run
+/// let mut player = Player::new(Recording::open("./bug-report.inprec")?, ctx.timing_function());
+/// player.set_looping(true);
+/// player.set_speed(0.5);
+///
+/// while !stop {
+///     let puppet = &mut ctx.puppets[0];
+///     player.update(puppet);
+///     puppet.update();
+/// }
+/// player.detach(&mut ctx.puppets[0]);
+/// ~~~
+///
+pub struct Player {
+    timing: extern "C" fn() -> f64,
+    recording: Recording,
+    speed: f64,
+    looping: bool,
+    paused: bool,
+    position: f64,
+    last_clock: Option<f64>,
+    /* Index of the next frame to apply, and the value of each track up to it */
+    next: usize,
+    values: Vec<Option<(f32, f32)>>,
+}
+
+impl Player {
+    /// Create a player at the start of `recording`, reading the time from `timing`.
+    pub fn new(recording: Recording, timing: extern "C" fn() -> f64) -> Self {
+        let values = vec![None; recording.tracks.len()];
+
+        Player {
+            timing,
+            recording,
+            speed: 1.0,
+            looping: false,
+            paused: false,
+            position: 0.0,
+            last_clock: None,
+            next: 0,
+            values,
+        }
+    }
+
+    /// Get the recording being played.
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    /// Get the playback speed, 1 being real time.
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Set the playback speed, 1 being real time. Negative speeds are treated as 0.
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.max(0.0);
+    }
+
+    /// Check whether playback restarts at the end of the recording.
+    pub fn looping(&self) -> bool {
+        self.looping
+    }
+
+    /// Set whether playback restarts at the end of the recording.
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    /// Pause or resume playback.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Get the playback position in seconds.
+    pub fn position(&self) -> f64 {
+        self.position
+    }
+
+    /// Jump to `position` seconds into the recording, clamped to its length. NaN seeks to the start.
+    pub fn seek(&mut self, position: f64) {
+        let position = match position.is_nan() {
+            true => 0.0,
+            false => position.clamp(0.0, self.recording.duration()),
+        };
+        if position < self.position {
+            self.rewind();
+        }
+
+        self.position = position;
+        self.catch_up();
+    }
+
+    /// Check whether playback has reached the end of a recording that does not loop.
+    pub fn is_finished(&self) -> bool {
+        !self.looping && self.position >= self.recording.duration()
+    }
+
+    /// Advance playback by the time passed since the last update and set the recorded
+    /// values on `puppet`, suspending its mixer, see `Inochi2DPuppet::set_mixer_suspended`.
+    pub fn update(&mut self, puppet: &mut Inochi2DPuppet) {
+        let now = (self.timing)();
+        self.advance_to(now);
+
+        puppet.set_mixer_suspended(true);
+        for (track, value) in self.recording.tracks.iter().zip(self.values.iter()) {
+            if let (Some((x, y)), Some(mut param)) = (value, puppet.param_mut(&track.name)) {
+                param.set_value(*x, *y);
+            }
+        }
+    }
+
+    /// Stop replaying onto `puppet`, resuming its mixer.
+    pub fn detach(&self, puppet: &mut Inochi2DPuppet) {
+        puppet.set_mixer_suspended(false);
+    }
+
+    /* Forget the applied frames so playback can move backwards */
+    fn rewind(&mut self) {
+        self.next = 0;
+        self.values.iter_mut().for_each(|value| *value = None);
+    }
+
+    /* Apply every frame up to the current position */
+    fn catch_up(&mut self) {
+        while let Some(frame) = self.recording.frames.get(self.next) {
+            if frame.time > self.position {
+                break;
+            }
+
+            for &(index, x, y) in frame.values.iter() {
+                self.values[index as usize] = Some((x, y));
+            }
+            self.next += 1;
+        }
+    }
+
+    fn advance_to(&mut self, now: f64) {
+        let elapsed = match self.last_clock.replace(now) {
+            Some(last) if !self.paused => (now - last).max(0.0) * self.speed,
+            _ => 0.0,
+        };
+
+        let duration = self.recording.duration();
+        let mut position = self.position + elapsed;
+        if position > duration {
+            /* An infinite speed has no meaningful place to wrap to, stop at the end instead */
+            if self.looping && duration > 0.0 && position.is_finite() {
+                position %= duration;
+                self.rewind();
+            } else {
+                position = duration;
+            }
+        }
+
+        self.position = position;
+        self.catch_up();
+    }
+
+    /* The current value of each recorded parameter that has one */
+    #[cfg(test)]
+    fn current(&self) -> Vec<(&str, (f32, f32))> {
+        self.recording
+            .tracks
+            .iter()
+            .zip(self.values.iter())
+            .filter_map(|(track, value)| Some((track.name.as_str(), (*value)?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn timing() -> f64 {
+        0.0
+    }
+
+    fn recording() -> Recording {
+        let mut recorder = Recorder::new(timing);
+        for frame in 0..=10 {
+            let head = if frame < 5 { 0.0 } else { frame as f32 };
+            recorder.capture_at(
+                100.0 + frame as f64 * 0.1,
+                vec![
+                    ("HeadX".to_string(), ParamValue::Scalar(head)),
+                    ("Eyes".to_string(), ParamValue::Vec2(frame as f32, 1.0)),
+                ],
+            );
+        }
+
+        recorder.finish()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let recording = recording();
+        assert_eq!(recording.frame_count(), 11);
+        assert!((recording.duration() - 1.0).abs() < 1e-9);
+        /* HeadX only changes on frames 0 and 5 to 10 */
+        assert_eq!(
+            recording
+                .frames
+                .iter()
+                .map(|frame| frame.values.len())
+                .sum::<usize>(),
+            11 + 7
+        );
+
+        let data = recording.to_bytes().unwrap();
+        assert_eq!(Recording::parse(&data).unwrap(), recording);
+        assert!(matches!(
+            Recording::parse(&data[..data.len() - 1]),
+            Err(RecordingError::UnexpectedEof { .. })
+        ));
+    }
+
+    #[test]
+    fn test_player() {
+        let mut player = Player::new(recording(), timing);
+
+        player.advance_to(10.0);
+        assert_eq!(
+            player.current(),
+            vec![("HeadX", (0.0, 0.0)), ("Eyes", (0.0, 1.0))]
+        );
+
+        player.set_speed(2.0);
+        player.advance_to(10.25);
+        assert_eq!(
+            player.current(),
+            vec![("HeadX", (5.0, 0.0)), ("Eyes", (5.0, 1.0))]
+        );
+
+        player.seek(0.15);
+        assert_eq!(
+            player.current(),
+            vec![("HeadX", (0.0, 0.0)), ("Eyes", (1.0, 1.0))]
+        );
+
+        /* Past the end playback stops, or wraps around when looping */
+        player.advance_to(11.0);
+        assert!(player.is_finished());
+        assert_eq!(player.current()[0], ("HeadX", (10.0, 0.0)));
+
+        player.set_looping(true);
+        player.seek(0.9);
+        player.advance_to(11.1);
+        assert!((player.position() - 0.1).abs() < 1e-9);
+        assert_eq!(player.current()[1], ("Eyes", (1.0, 1.0)));
+    }
+
+    #[test]
+    fn test_invalid_timestamps() {
+        let data = recording().to_bytes().unwrap();
+        /* The last frame holds HeadX and Eyes, its time starts 8 + 2 + 6 + 10 bytes from the end */
+        let time = data.len() - 26;
+        for bad in [f64::NAN, -1.0, 0.5, f64::INFINITY] {
+            let mut data = data.clone();
+            data[time..time + 8].copy_from_slice(&bad.to_be_bytes());
+            assert!(matches!(
+                Recording::parse(&data),
+                Err(RecordingError::InvalidTimestamp { frame: 10, .. })
+            ));
+        }
+
+        /* A misbehaving clock never produces such a recording */
+        let mut recorder = Recorder::new(timing);
+        for (value, now) in [5.0, 4.0, f64::NAN, 6.0].into_iter().enumerate() {
+            recorder.capture_at(
+                now,
+                vec![("X".to_string(), ParamValue::Scalar(value as f32))],
+            );
+        }
+        let recording = recorder.finish();
+        let data = recording.to_bytes().unwrap();
+        assert_eq!(Recording::parse(&data).unwrap(), recording);
+        assert_eq!(recording.duration(), 1.0);
+
+        let mut player = Player::new(recording, timing);
+        player.seek(f64::NAN);
+        assert_eq!(player.position(), 0.0);
+        player.set_looping(true);
+        player.set_speed(f64::INFINITY);
+        player.advance_to(0.0);
+        player.advance_to(1.0);
+        assert_eq!(player.position(), 1.0);
+    }
+
+    #[test]
+    fn test_too_large() {
+        let mut recording = recording();
+        recording.tracks[0].name = "X".repeat(u16::MAX as usize + 1);
+        let mut data = Vec::new();
+        assert!(matches!(
+            recording.write(&mut data),
+            Err(RecordingError::TooLarge {
+                what: "parameter name",
+                ..
+            })
+        ));
+        assert!(data.is_empty());
+    }
+}
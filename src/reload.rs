@@ -0,0 +1,90 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::Inochi2DError;
+
+/// The outcome of reloading a puppet whose file changed.
+#[derive(Debug)]
+pub enum HotReloadEvent {
+    /// The puppet at `index` in `Inochi2D::puppets` was replaced.
+    Reloaded { index: usize, id: PathBuf },
+    /// Reloading the puppet at `index` failed, the old puppet was kept.
+    Failed {
+        index: usize,
+        id: PathBuf,
+        error: Inochi2DError,
+    },
+}
+
+/* Tracks modification times of the puppets loaded in a context */
+pub(crate) struct HotReloader {
+    interval: Duration,
+    last_check: Option<Instant>,
+    modified: HashMap<PathBuf, SystemTime>,
+}
+
+impl HotReloader {
+    pub(crate) fn new(interval: Duration) -> Self {
+        HotReloader {
+            interval,
+            last_check: None,
+            modified: HashMap::new(),
+        }
+    }
+
+    /* Returns true if at least `interval` has passed since the last check */
+    pub(crate) fn due(&mut self, now: Instant) -> bool {
+        match self.last_check {
+            Some(last) if now.duration_since(last) < self.interval => false,
+            _ => {
+                self.last_check = Some(now);
+                true
+            }
+        }
+    }
+
+    /* Record the modification time of `id`, returns true if it changed since it was last seen.
+    Only the first call after a change sees it, so call this once per file and check */
+    pub(crate) fn changed(&mut self, id: &Path, modified: SystemTime) -> bool {
+        match self.modified.insert(id.to_path_buf(), modified) {
+            Some(previous) => previous != modified,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_change_detection() {
+        let mut reloader = HotReloader::new(Duration::from_millis(500));
+        let id = Path::new("Ada.inx");
+        let t0 = SystemTime::UNIX_EPOCH;
+        let t1 = t0 + Duration::from_secs(1);
+
+        assert!(!reloader.changed(id, t0));
+        assert!(!reloader.changed(id, t0));
+        assert!(reloader.changed(id, t1));
+        assert!(!reloader.changed(id, t1));
+    }
+
+    #[test]
+    fn test_interval() {
+        let mut reloader = HotReloader::new(Duration::from_millis(500));
+        let now = Instant::now();
+
+        assert!(reloader.due(now));
+        assert!(!reloader.due(now + Duration::from_millis(100)));
+        assert!(reloader.due(now + Duration::from_millis(600)));
+    }
+}
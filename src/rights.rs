@@ -0,0 +1,309 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+use std::fmt;
+
+use crate::format::{
+    self,
+    model::{AllowedModification, AllowedRedistribution, AllowedUsers, PuppetMeta, UsageRights},
+};
+
+/// Who the host application is using a puppet as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PuppetUser {
+    /// The author of the puppet.
+    Author,
+    /// Someone the author has licensed the puppet to.
+    Licensee,
+    /// Anyone else.
+    Anyone,
+}
+
+/// How the host application modifies puppets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModificationUse {
+    /// Puppets are not modified.
+    None,
+    /// Puppets are modified for personal use only.
+    Personal,
+    /// Modified puppets are redistributed.
+    Redistribute,
+}
+
+/// What happens to puppets whose usage rights conflict with the policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Enforcement {
+    /// Refuse to load the puppet, returning `Inochi2DError::Rights`.
+    Refuse,
+    /// Load the puppet but record the violations on it and never draw it.
+    Flag,
+}
+
+/// A conflict between a puppet's usage rights and a `RightsPolicy`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RightsViolation {
+    /// The puppet has no usage rights and the policy requires them.
+    MissingRights,
+    /// The puppet's metadata or usage rights could not be read.
+    UnreadableRights,
+    /// The puppet may only be used by `allowed`.
+    User { allowed: AllowedUsers },
+    /// The puppet may not be used commercially.
+    Commercial,
+    /// The puppet may not be used in violent content.
+    Violence,
+    /// The puppet may not be used in sexual content.
+    Sexual,
+    /// The puppet may not be redistributed.
+    Redistribution { allowed: AllowedRedistribution },
+    /// The puppet may not be modified the way the host does.
+    Modification { allowed: AllowedModification },
+}
+
+impl fmt::Display for RightsViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RightsViolation::MissingRights => write!(f, "puppet declares no usage rights"),
+            RightsViolation::UnreadableRights => {
+                write!(f, "puppet metadata or usage rights are malformed")
+            }
+            RightsViolation::User { allowed } => {
+                write!(f, "puppet may only be used by users matching {}", allowed)
+            }
+            RightsViolation::Commercial => write!(f, "puppet may not be used commercially"),
+            RightsViolation::Violence => write!(f, "puppet may not be used in violent content"),
+            RightsViolation::Sexual => write!(f, "puppet may not be used in sexual content"),
+            RightsViolation::Redistribution { allowed } => {
+                write!(f, "puppet redistribution is limited to {}", allowed)
+            }
+            RightsViolation::Modification { allowed } => {
+                write!(f, "puppet modification is limited to {}", allowed)
+            }
+        }
+    }
+}
+
+/// The uses a host application makes of puppets, checked against each puppet's usage rights.
+///
+/// The default policy declares the most restrictive use: non-commercial, personal use by
+/// anyone with no violent or sexual content, no redistribution and no modification.
+///
+/// # Example
+///
+/// ~~~no_run
+/// let policy = RightsPolicy {
+///     commercial: true,
+///     ..RightsPolicy::default()
+/// };
+///
+/// let ctx = Inochi2DBuilder::new()
+///     .rights_policy(policy)
+///     .puppet("./puppets/Ada.inx")
+///     .build()?;
+/// ~~~
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RightsPolicy {
+    pub user: PuppetUser,
+    pub commercial: bool,
+    pub violence: bool,
+    pub sexual: bool,
+    pub redistribution: bool,
+    pub modification: ModificationUse,
+    /// Whether puppets without usage rights in their metadata are a violation.
+    pub require_rights: bool,
+    pub enforcement: Enforcement,
+}
+
+impl Default for RightsPolicy {
+    fn default() -> Self {
+        RightsPolicy {
+            user: PuppetUser::Anyone,
+            commercial: false,
+            violence: false,
+            sexual: false,
+            redistribution: false,
+            modification: ModificationUse::None,
+            require_rights: false,
+            enforcement: Enforcement::Refuse,
+        }
+    }
+}
+
+impl RightsPolicy {
+    /// Check a puppet's metadata against the policy.
+    ///
+    /// # Returns
+    ///
+    /// Every violation found, an empty `Vec` if the puppet may be used.
+    ///
+    pub fn check(&self, meta: Option<&PuppetMeta>) -> Vec<RightsViolation> {
+        match meta.and_then(|meta| meta.rights.as_ref()) {
+            Some(rights) => self.check_rights(rights),
+            None if self.require_rights => vec![RightsViolation::MissingRights],
+            None => Vec::new(),
+        }
+    }
+
+    /// Check the metadata of INP/INX data against the policy.
+    ///
+    /// Metadata that can't be read is a `RightsViolation::UnreadableRights`, so a malformed
+    /// usage rights block can't be used to get around the policy.
+    ///
+    /// # Returns
+    ///
+    /// The metadata if it could be read, and every violation found.
+    ///
+    pub fn check_bytes(&self, data: &[u8]) -> (Option<PuppetMeta>, Vec<RightsViolation>) {
+        match format::read_meta_from_bytes(data) {
+            Ok(meta) => {
+                let violations = self.check(Some(&meta));
+                (Some(meta), violations)
+            }
+            Err(_) => (None, vec![RightsViolation::UnreadableRights]),
+        }
+    }
+
+    /// Check a puppet's usage rights against the policy.
+    pub fn check_rights(&self, rights: &UsageRights) -> Vec<RightsViolation> {
+        let mut violations = Vec::new();
+
+        let user_allowed = match &rights.allowed_users {
+            AllowedUsers::Everyone => true,
+            AllowedUsers::OnlyLicensee => self.user != PuppetUser::Anyone,
+            AllowedUsers::OnlyAuthor => self.user == PuppetUser::Author,
+            AllowedUsers::Other(_) => self.user == PuppetUser::Author,
+        };
+        if !user_allowed {
+            violations.push(RightsViolation::User {
+                allowed: rights.allowed_users.clone(),
+            });
+        }
+
+        if self.commercial && !rights.allow_commercial {
+            violations.push(RightsViolation::Commercial);
+        }
+        if self.violence && !rights.allow_violence {
+            violations.push(RightsViolation::Violence);
+        }
+        if self.sexual && !rights.allow_sexual {
+            violations.push(RightsViolation::Sexual);
+        }
+
+        let redistribution_allowed = match &rights.allow_redistribution {
+            AllowedRedistribution::ViaSource | AllowedRedistribution::CopyleftLicense => true,
+            AllowedRedistribution::Prohibited | AllowedRedistribution::Other(_) => false,
+        };
+        if self.redistribution && !redistribution_allowed {
+            violations.push(RightsViolation::Redistribution {
+                allowed: rights.allow_redistribution.clone(),
+            });
+        }
+
+        let modification_allowed = matches!(
+            (&rights.allow_modification, self.modification),
+            (_, ModificationUse::None)
+                | (AllowedModification::AllowRedistribute, _)
+                | (
+                    AllowedModification::AllowPersonal,
+                    ModificationUse::Personal
+                )
+        );
+        if !modification_allowed {
+            violations.push(RightsViolation::Modification {
+                allowed: rights.allow_modification.clone(),
+            });
+        }
+
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{model::Extra, Container};
+
+    fn rights() -> UsageRights {
+        UsageRights {
+            allowed_users: AllowedUsers::OnlyLicensee,
+            allow_violence: false,
+            allow_sexual: false,
+            allow_commercial: false,
+            allow_redistribution: AllowedRedistribution::Prohibited,
+            allow_modification: AllowedModification::AllowPersonal,
+            require_attribution: true,
+            extra: Extra::new(),
+        }
+    }
+
+    #[test]
+    fn test_default_policy() {
+        let policy = RightsPolicy {
+            user: PuppetUser::Licensee,
+            ..RightsPolicy::default()
+        };
+
+        assert!(policy.check_rights(&rights()).is_empty());
+        assert!(policy.check(None).is_empty());
+    }
+
+    #[test]
+    fn test_violations() {
+        let policy = RightsPolicy {
+            user: PuppetUser::Anyone,
+            commercial: true,
+            violence: true,
+            redistribution: true,
+            modification: ModificationUse::Redistribute,
+            require_rights: true,
+            ..RightsPolicy::default()
+        };
+
+        assert_eq!(
+            policy.check_rights(&rights()),
+            vec![
+                RightsViolation::User {
+                    allowed: AllowedUsers::OnlyLicensee
+                },
+                RightsViolation::Commercial,
+                RightsViolation::Violence,
+                RightsViolation::Redistribution {
+                    allowed: AllowedRedistribution::Prohibited
+                },
+                RightsViolation::Modification {
+                    allowed: AllowedModification::AllowPersonal
+                },
+            ]
+        );
+        assert_eq!(policy.check(None), vec![RightsViolation::MissingRights]);
+    }
+
+    #[test]
+    fn test_unreadable_rights() {
+        let data = |json: &str| {
+            Container {
+                json: json.into(),
+                textures: Vec::new(),
+                vendor: Vec::new(),
+            }
+            .to_bytes()
+            .unwrap()
+        };
+        let policy = RightsPolicy::default();
+
+        /* `allowRedistribution` is required */
+        let (meta, violations) = policy.check_bytes(&data(
+            r#"{"meta":{"rights":{"allowedUsers":"everyone","allowModification":"prohibit"}}}"#,
+        ));
+        assert!(meta.is_none());
+        assert_eq!(violations, vec![RightsViolation::UnreadableRights]);
+
+        let (meta, violations) = policy.check_bytes(&data(r#"{"meta":{}}"#));
+        assert!(meta.is_some() && violations.is_empty());
+    }
+}
@@ -0,0 +1,583 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+use super::TextureError;
+
+/// Size of the header this crate puts before BC7 blocks, a big-endian `u32` width followed by
+/// a big-endian `u32` height. It is not defined by Inochi2D, see `TextureEncoding::Bc7`.
+pub const HEADER_SIZE: usize = 8;
+/// Size of a single compressed 4x4 block.
+pub const BLOCK_SIZE: usize = 16;
+
+/* Per mode layout: subsets, partition bits, rotation bits, index selection bits, colour
+bits, alpha bits, per-endpoint p-bits, shared p-bits, index bits, secondary index bits */
+struct Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    index2_bits: u32,
+}
+
+#[rustfmt::skip]
+const MODES: [Mode; 8] = [
+    Mode { subsets: 3, partition_bits: 4, rotation_bits: 0, selection_bits: 0, color_bits: 4, alpha_bits: 0, endpoint_pbits: true,  shared_pbits: false, index_bits: 3, index2_bits: 0 },
+    Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, selection_bits: 0, color_bits: 6, alpha_bits: 0, endpoint_pbits: false, shared_pbits: true,  index_bits: 3, index2_bits: 0 },
+    Mode { subsets: 3, partition_bits: 6, rotation_bits: 0, selection_bits: 0, color_bits: 5, alpha_bits: 0, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index2_bits: 0 },
+    Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, selection_bits: 0, color_bits: 7, alpha_bits: 0, endpoint_pbits: true,  shared_pbits: false, index_bits: 2, index2_bits: 0 },
+    Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, selection_bits: 1, color_bits: 5, alpha_bits: 6, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index2_bits: 3 },
+    Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, selection_bits: 0, color_bits: 7, alpha_bits: 8, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index2_bits: 2 },
+    Mode { subsets: 1, partition_bits: 0, rotation_bits: 0, selection_bits: 0, color_bits: 7, alpha_bits: 7, endpoint_pbits: true,  shared_pbits: false, index_bits: 4, index2_bits: 0 },
+    Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, selection_bits: 0, color_bits: 5, alpha_bits: 5, endpoint_pbits: true,  shared_pbits: false, index_bits: 2, index2_bits: 0 },
+];
+
+#[rustfmt::skip]
+pub(super) const PARTITIONS_2: [[u8; 16]; 64] = [
+    [0,0,1,1,0,0,1,1,0,0,1,1,0,0,1,1], [0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,1],
+    [0,1,1,1,0,1,1,1,0,1,1,1,0,1,1,1], [0,0,0,1,0,0,1,1,0,0,1,1,0,1,1,1],
+    [0,0,0,0,0,0,0,1,0,0,0,1,0,0,1,1], [0,0,1,1,0,1,1,1,0,1,1,1,1,1,1,1],
+    [0,0,0,1,0,0,1,1,0,1,1,1,1,1,1,1], [0,0,0,0,0,0,0,1,0,0,1,1,0,1,1,1],
+    [0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,1], [0,0,1,1,0,1,1,1,1,1,1,1,1,1,1,1],
+    [0,0,0,0,0,0,0,1,0,1,1,1,1,1,1,1], [0,0,0,0,0,0,0,0,0,0,0,1,0,1,1,1],
+    [0,0,0,1,0,1,1,1,1,1,1,1,1,1,1,1], [0,0,0,0,0,0,0,0,1,1,1,1,1,1,1,1],
+    [0,0,0,0,1,1,1,1,1,1,1,1,1,1,1,1], [0,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1],
+    [0,0,0,0,1,0,0,0,1,1,1,0,1,1,1,1], [0,1,1,1,0,0,0,1,0,0,0,0,0,0,0,0],
+    [0,0,0,0,0,0,0,0,1,0,0,0,1,1,1,0], [0,1,1,1,0,0,1,1,0,0,0,1,0,0,0,0],
+    [0,0,1,1,0,0,0,1,0,0,0,0,0,0,0,0], [0,0,0,0,1,0,0,0,1,1,0,0,1,1,1,0],
+    [0,0,0,0,0,0,0,0,1,0,0,0,1,1,0,0], [0,1,1,1,0,0,1,1,0,0,1,1,0,0,0,1],
+    [0,0,1,1,0,0,0,1,0,0,0,1,0,0,0,0], [0,0,0,0,1,0,0,0,1,0,0,0,1,1,0,0],
+    [0,1,1,0,0,1,1,0,0,1,1,0,0,1,1,0], [0,0,1,1,0,1,1,0,0,1,1,0,1,1,0,0],
+    [0,0,0,1,0,1,1,1,1,1,1,0,1,0,0,0], [0,0,0,0,1,1,1,1,1,1,1,1,0,0,0,0],
+    [0,1,1,1,0,0,0,1,1,0,0,0,1,1,1,0], [0,0,1,1,1,0,0,1,1,0,0,1,1,1,0,0],
+    [0,1,0,1,0,1,0,1,0,1,0,1,0,1,0,1], [0,0,0,0,1,1,1,1,0,0,0,0,1,1,1,1],
+    [0,1,0,1,1,0,1,0,0,1,0,1,1,0,1,0], [0,0,1,1,0,0,1,1,1,1,0,0,1,1,0,0],
+    [0,0,1,1,1,1,0,0,0,0,1,1,1,1,0,0], [0,1,0,1,0,1,0,1,1,0,1,0,1,0,1,0],
+    [0,1,1,0,1,0,0,1,0,1,1,0,1,0,0,1], [0,1,0,1,1,0,1,0,1,0,1,0,0,1,0,1],
+    [0,1,1,1,0,0,1,1,1,1,0,0,1,1,1,0], [0,0,0,1,0,0,1,1,1,1,0,0,1,0,0,0],
+    [0,0,1,1,0,0,1,0,0,1,0,0,1,1,0,0], [0,0,1,1,1,0,1,1,1,1,0,1,1,1,0,0],
+    [0,1,1,0,1,0,0,1,1,0,0,1,0,1,1,0], [0,0,1,1,1,1,0,0,1,1,0,0,0,0,1,1],
+    [0,1,1,0,0,1,1,0,1,0,0,1,1,0,0,1], [0,0,0,0,0,1,1,0,0,1,1,0,0,0,0,0],
+    [0,1,0,0,1,1,1,0,0,1,0,0,0,0,0,0], [0,0,1,0,0,1,1,1,0,0,1,0,0,0,0,0],
+    [0,0,0,0,0,0,1,0,0,1,1,1,0,0,1,0], [0,0,0,0,0,1,0,0,1,1,1,0,0,1,0,0],
+    [0,1,1,0,1,1,0,0,1,0,0,1,0,0,1,1], [0,0,1,1,0,1,1,0,1,1,0,0,1,0,0,1],
+    [0,1,1,0,0,0,1,1,1,0,0,1,1,1,0,0], [0,0,1,1,1,0,0,1,1,1,0,0,0,1,1,0],
+    [0,1,1,0,1,1,0,0,1,1,0,0,1,0,0,1], [0,1,1,0,0,0,1,1,0,0,1,1,1,0,0,1],
+    [0,1,1,1,1,1,1,0,1,0,0,0,0,0,0,1], [0,0,0,1,1,0,0,0,1,1,1,0,0,1,1,1],
+    [0,0,0,0,1,1,1,1,0,0,1,1,0,0,1,1], [0,0,1,1,0,0,1,1,1,1,1,1,0,0,0,0],
+    [0,0,1,0,0,0,1,0,1,1,1,0,1,1,1,0], [0,1,0,0,0,1,0,0,0,1,1,1,0,1,1,1],
+];
+
+#[rustfmt::skip]
+pub(super) const PARTITIONS_3: [[u8; 16]; 64] = [
+    [0,0,1,1,0,0,1,1,0,2,2,1,2,2,2,2], [0,0,0,1,0,0,1,1,2,2,1,1,2,2,2,1],
+    [0,0,0,0,2,0,0,1,2,2,1,1,2,2,1,1], [0,2,2,2,0,0,2,2,0,0,1,1,0,1,1,1],
+    [0,0,0,0,0,0,0,0,1,1,2,2,1,1,2,2], [0,0,1,1,0,0,1,1,0,0,2,2,0,0,2,2],
+    [0,0,2,2,0,0,2,2,1,1,1,1,1,1,1,1], [0,0,1,1,0,0,1,1,2,2,1,1,2,2,1,1],
+    [0,0,0,0,0,0,0,0,1,1,1,1,2,2,2,2], [0,0,0,0,1,1,1,1,1,1,1,1,2,2,2,2],
+    [0,0,0,0,1,1,1,1,2,2,2,2,2,2,2,2], [0,0,1,2,0,0,1,2,0,0,1,2,0,0,1,2],
+    [0,1,1,2,0,1,1,2,0,1,1,2,0,1,1,2], [0,1,2,2,0,1,2,2,0,1,2,2,0,1,2,2],
+    [0,0,1,1,0,1,1,2,1,1,2,2,1,2,2,2], [0,0,1,1,2,0,0,1,2,2,0,0,2,2,2,0],
+    [0,0,0,1,0,0,1,1,0,1,1,2,1,1,2,2], [0,1,1,1,0,0,1,1,2,0,0,1,2,2,0,0],
+    [0,0,0,0,1,1,2,2,1,1,2,2,1,1,2,2], [0,0,2,2,0,0,2,2,0,0,2,2,1,1,1,1],
+    [0,1,1,1,0,1,1,1,0,2,2,2,0,2,2,2], [0,0,0,1,0,0,0,1,2,2,2,1,2,2,2,1],
+    [0,0,0,0,0,0,1,1,0,1,2,2,0,1,2,2], [0,0,0,0,1,1,0,0,2,2,1,0,2,2,1,0],
+    [0,1,2,2,0,1,2,2,0,0,1,1,0,0,0,0], [0,0,1,2,0,0,1,2,1,1,2,2,2,2,2,2],
+    [0,1,1,0,1,2,2,1,1,2,2,1,0,1,1,0], [0,0,0,0,0,1,1,0,1,2,2,1,1,2,2,1],
+    [0,0,2,2,1,1,0,2,1,1,0,2,0,0,2,2], [0,1,1,0,0,1,1,0,2,0,0,2,2,2,2,2],
+    [0,0,1,1,0,1,2,2,0,1,2,2,0,0,1,1], [0,0,0,0,2,0,0,0,2,2,1,1,2,2,2,1],
+    [0,0,0,0,0,0,0,2,1,1,2,2,1,2,2,2], [0,2,2,2,0,0,2,2,0,0,1,2,0,0,1,1],
+    [0,0,1,1,0,0,1,2,0,0,2,2,0,2,2,2], [0,1,2,0,0,1,2,0,0,1,2,0,0,1,2,0],
+    [0,0,0,0,1,1,1,1,2,2,2,2,0,0,0,0], [0,1,2,0,1,2,0,1,2,0,1,2,0,1,2,0],
+    [0,1,2,0,2,0,1,2,1,2,0,1,0,1,2,0], [0,0,1,1,2,2,0,0,1,1,2,2,0,0,1,1],
+    [0,0,1,1,1,1,2,2,2,2,0,0,0,0,1,1], [0,1,0,1,0,1,0,1,2,2,2,2,2,2,2,2],
+    [0,0,0,0,0,0,0,0,2,1,2,1,2,1,2,1], [0,0,2,2,1,1,2,2,0,0,2,2,1,1,2,2],
+    [0,0,2,2,0,0,1,1,0,0,2,2,0,0,1,1], [0,2,2,0,1,2,2,1,0,2,2,0,1,2,2,1],
+    [0,1,0,1,2,2,2,2,2,2,2,2,0,1,0,1], [0,0,0,0,2,1,2,1,2,1,2,1,2,1,2,1],
+    [0,1,0,1,0,1,0,1,0,1,0,1,2,2,2,2], [0,2,2,2,0,1,1,1,0,2,2,2,0,1,1,1],
+    [0,0,0,2,1,1,1,2,0,0,0,2,1,1,1,2], [0,0,0,0,2,1,1,2,2,1,1,2,2,1,1,2],
+    [0,2,2,2,0,1,1,1,0,1,1,1,0,2,2,2], [0,0,0,2,1,1,1,2,1,1,1,2,0,0,0,2],
+    [0,1,1,0,0,1,1,0,0,1,1,0,2,2,2,2], [0,0,0,0,0,0,0,0,2,1,1,2,2,1,1,2],
+    [0,1,1,0,0,1,1,0,2,2,2,2,2,2,2,2], [0,0,2,2,0,0,1,1,0,0,1,1,0,0,2,2],
+    [0,0,2,2,1,1,2,2,1,1,2,2,0,0,2,2], [0,0,0,0,0,0,0,0,0,0,0,0,2,1,1,2],
+    [0,0,0,2,0,0,0,1,0,0,0,2,0,0,0,1], [0,2,2,2,1,2,2,2,0,2,2,2,1,2,2,2],
+    [0,1,0,1,2,2,2,2,2,2,2,2,2,2,2,2], [0,1,1,1,2,0,1,1,2,2,0,1,2,2,2,0],
+];
+
+/* Index of the pixel whose index has its top bit implied, for the second subset of the
+two-subset partitions and the second and third subsets of the three-subset partitions */
+#[rustfmt::skip]
+pub(super) const ANCHORS_2: [u8; 64] = [
+    15,15,15,15,15,15,15,15, 15,15,15,15,15,15,15,15,
+    15, 2, 8, 2, 2, 8, 8,15,  2, 8, 2, 2, 8, 8, 2, 2,
+    15,15, 6, 8, 2, 8,15,15,  2, 8, 2, 2, 2,15,15, 6,
+     6, 2, 6, 8,15,15, 2, 2, 15,15,15,15,15, 2, 2,15,
+];
+
+#[rustfmt::skip]
+pub(super) const ANCHORS_3A: [u8; 64] = [
+     3, 3,15,15, 8, 3,15,15,  8, 8, 6, 6, 6, 5, 3, 3,
+     3, 3, 8,15, 3, 3, 6,10,  5, 8, 8, 6, 8, 5,15,15,
+     8,15, 3, 5, 6,10, 8,15, 15, 3,15, 5,15,15,15,15,
+     3,15, 5, 5, 5, 8, 5,10,  5,10, 8,13,15,12, 3, 3,
+];
+
+#[rustfmt::skip]
+pub(super) const ANCHORS_3B: [u8; 64] = [
+    15, 8, 8, 3,15,15, 3, 8, 15,15,15,15,15,15,15, 8,
+    15, 8,15, 3,15, 8,15, 8,  3,15, 6,10,15,15,10, 8,
+    15, 3,15,10,10, 8, 9,10,  6,15, 8,15, 3, 6, 6, 8,
+    15, 3,15,15,15,15,15,15, 15,15,15,15, 3,15,15, 8,
+];
+
+pub(super) const WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+pub(super) const WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+pub(super) const WEIGHTS_4: [u32; 16] =
+    [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn weights(bits: u32) -> &'static [u32] {
+    match bits {
+        2 => &WEIGHTS_2,
+        3 => &WEIGHTS_3,
+        _ => &WEIGHTS_4,
+    }
+}
+
+pub(super) fn interpolate(e0: u8, e1: u8, weight: u32) -> u8 {
+    (((64 - weight) * e0 as u32 + weight * e1 as u32 + 32) >> 6) as u8
+}
+
+/* Reads bits from a block, least significant bit first */
+struct Bits {
+    value: u128,
+}
+
+impl Bits {
+    fn read(&mut self, count: u32) -> u8 {
+        let bits = (self.value & ((1u128 << count) - 1)) as u8;
+        self.value >>= count;
+        bits
+    }
+}
+
+/* Expand a `bits` wide endpoint to 8 bits by replicating its top bits */
+fn expand(value: u8, bits: u32) -> u8 {
+    let value = (value as u32) << (8 - bits);
+    (value | value >> bits) as u8
+}
+
+/* Decode one 16 byte block into 16 RGBA8 pixels in row-major order */
+pub(super) fn decode_block(block: &[u8; BLOCK_SIZE]) -> [[u8; 4]; 16] {
+    let mut bits = Bits {
+        value: u128::from_le_bytes(*block),
+    };
+
+    let mode_index = match block[0].trailing_zeros() {
+        index @ 0..=7 => index as usize,
+        /* Reserved mode, decoders output transparent black */
+        _ => return [[0; 4]; 16],
+    };
+    let mode = &MODES[mode_index];
+    bits.read(mode_index as u32 + 1);
+
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let selection = bits.read(mode.selection_bits);
+
+    /* Endpoints are stored channel by channel, [subset][endpoint][channel] */
+    let mut endpoints = [[[0u8; 4]; 2]; 3];
+    for channel in 0..4 {
+        let width = if channel < 3 {
+            mode.color_bits
+        } else {
+            mode.alpha_bits
+        };
+        for subset in endpoints.iter_mut().take(mode.subsets) {
+            for endpoint in subset.iter_mut() {
+                endpoint[channel] = bits.read(width);
+            }
+        }
+    }
+
+    let mut pbits = [[0u8; 2]; 3];
+    if mode.endpoint_pbits {
+        for subset in pbits.iter_mut().take(mode.subsets) {
+            subset[0] = bits.read(1);
+            subset[1] = bits.read(1);
+        }
+    } else if mode.shared_pbits {
+        for subset in pbits.iter_mut().take(mode.subsets) {
+            let bit = bits.read(1);
+            *subset = [bit, bit];
+        }
+    }
+    let has_pbits = mode.endpoint_pbits || mode.shared_pbits;
+
+    for (subset, subset_pbits) in endpoints.iter_mut().zip(pbits).take(mode.subsets) {
+        for (endpoint, pbit) in subset.iter_mut().zip(subset_pbits) {
+            for (channel, value) in endpoint.iter_mut().enumerate() {
+                let width = if channel < 3 {
+                    mode.color_bits
+                } else {
+                    mode.alpha_bits
+                };
+                *value = match (width, has_pbits) {
+                    (0, _) => 0xFF,
+                    (width, true) => expand(*value << 1 | pbit, width + 1),
+                    (width, false) => expand(*value, width),
+                };
+            }
+        }
+    }
+
+    let subset_of = |pixel: usize| match mode.subsets {
+        2 => PARTITIONS_2[partition][pixel] as usize,
+        3 => PARTITIONS_3[partition][pixel] as usize,
+        _ => 0,
+    };
+    let is_anchor = |pixel: usize| match mode.subsets {
+        2 => pixel == 0 || pixel == ANCHORS_2[partition] as usize,
+        3 => {
+            pixel == 0
+                || pixel == ANCHORS_3A[partition] as usize
+                || pixel == ANCHORS_3B[partition] as usize
+        }
+        _ => pixel == 0,
+    };
+
+    let mut indices = [0u8; 16];
+    for (pixel, index) in indices.iter_mut().enumerate() {
+        *index = bits.read(mode.index_bits - is_anchor(pixel) as u32);
+    }
+    let mut indices2 = [0u8; 16];
+    if mode.index2_bits > 0 {
+        for (pixel, index) in indices2.iter_mut().enumerate() {
+            *index = bits.read(mode.index2_bits - (pixel == 0) as u32);
+        }
+    }
+
+    let mut pixels = [[0u8; 4]; 16];
+    for (pixel, out) in pixels.iter_mut().enumerate() {
+        let [e0, e1] = endpoints[subset_of(pixel)];
+
+        let (color_index, color_bits, alpha_index, alpha_bits) = match (mode.index2_bits, selection)
+        {
+            (0, _) => (
+                indices[pixel],
+                mode.index_bits,
+                indices[pixel],
+                mode.index_bits,
+            ),
+            (_, 0) => (
+                indices[pixel],
+                mode.index_bits,
+                indices2[pixel],
+                mode.index2_bits,
+            ),
+            _ => (
+                indices2[pixel],
+                mode.index2_bits,
+                indices[pixel],
+                mode.index_bits,
+            ),
+        };
+        let color_weight = weights(color_bits)[color_index as usize];
+        let alpha_weight = weights(alpha_bits)[alpha_index as usize];
+
+        for channel in 0..3 {
+            out[channel] = interpolate(e0[channel], e1[channel], color_weight);
+        }
+        out[3] = interpolate(e0[3], e1[3], alpha_weight);
+
+        match rotation {
+            1 => out.swap(0, 3),
+            2 => out.swap(1, 3),
+            3 => out.swap(2, 3),
+            _ => (),
+        }
+    }
+
+    pixels
+}
+
+/* Decode a BC7 texture with its size header into straight RGBA8 pixels */
+pub(super) fn decode(data: &[u8]) -> Result<(u32, u32, Vec<u8>), TextureError> {
+    if data.len() < HEADER_SIZE {
+        return Err(TextureError::Bc7("missing size header".into()));
+    }
+
+    let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+    let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+    let blocks_x = (width as usize).div_ceil(4);
+    let blocks_y = (height as usize).div_ceil(4);
+
+    let expected = blocks_x
+        .checked_mul(blocks_y)
+        .and_then(|blocks| blocks.checked_mul(BLOCK_SIZE))
+        .ok_or_else(|| TextureError::Bc7(format!("{}x{} is too large", width, height)))?;
+    let blocks = &data[HEADER_SIZE..];
+    if blocks.len() < expected {
+        return Err(TextureError::Bc7(format!(
+            "{}x{} needs {} bytes of blocks, found {}",
+            width,
+            height,
+            expected,
+            blocks.len()
+        )));
+    }
+
+    let stride = width as usize * 4;
+    let mut pixels = vec![0u8; stride * height as usize];
+    for (i, block) in blocks
+        .chunks_exact(BLOCK_SIZE)
+        .take(blocks_x * blocks_y)
+        .enumerate()
+    {
+        let decoded = decode_block(block.try_into().expect("chunks are BLOCK_SIZE long"));
+        let (bx, by) = (i % blocks_x * 4, i / blocks_x * 4);
+
+        for (pixel, rgba) in decoded.iter().enumerate() {
+            let (x, y) = (bx + pixel % 4, by + pixel / 4);
+            if x < width as usize && y < height as usize {
+                let offset = y * stride + x * 4;
+                pixels[offset..offset + 4].copy_from_slice(rgba);
+            }
+        }
+    }
+
+    Ok((width, height, pixels))
+}
+
+/* Writes bits into a block, least significant bit first */
+struct BitWriter {
+    value: u128,
+    offset: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, count: u32, bits: u32) {
+        self.value |= (bits as u128) << self.offset;
+        self.offset += count;
+    }
+}
+
+/* Quantize an endpoint to 7 bits per channel plus a shared p-bit, picking the closest */
+fn quantize_endpoint(endpoint: [f32; 4]) -> ([u8; 4], u8) {
+    let mut best = ([0u8; 4], 0u8, f32::MAX);
+    for pbit in 0..=1u8 {
+        let mut quantized = [0u8; 4];
+        let mut error = 0.0;
+        for (q, &value) in quantized.iter_mut().zip(endpoint.iter()) {
+            *q = ((value - pbit as f32) / 2.0).round().clamp(0.0, 127.0) as u8;
+            let expanded = (*q << 1 | pbit) as f32;
+            error += (expanded - value) * (expanded - value);
+        }
+        if error < best.2 {
+            best = (quantized, pbit, error);
+        }
+    }
+
+    (best.0, best.1)
+}
+
+/* Encode 16 straight RGBA8 pixels as a single mode 6 block */
+pub(super) fn encode_block(pixels: &[[u8; 4]; 16]) -> [u8; BLOCK_SIZE] {
+    let texels = pixels.map(|p| p.map(|c| c as f32));
+
+    let mut mean = [0f32; 4];
+    for t in texels.iter() {
+        for c in 0..4 {
+            mean[c] += t[c] / 16.0;
+        }
+    }
+
+    /* Fit the endpoints along the principal axis of the colours */
+    let mut covariance = [[0f32; 4]; 4];
+    for t in texels.iter() {
+        for i in 0..4 {
+            for j in 0..4 {
+                covariance[i][j] += (t[i] - mean[i]) * (t[j] - mean[j]);
+            }
+        }
+    }
+    let mut axis = [1f32; 4];
+    for _ in 0..8 {
+        let mut next = [0f32; 4];
+        for i in 0..4 {
+            next[i] = (0..4).map(|j| covariance[i][j] * axis[j]).sum();
+        }
+        let length = next.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if length < 1e-6 {
+            axis = [0.0; 4];
+            break;
+        }
+        axis = next.map(|v| v / length);
+    }
+
+    let project = |t: &[f32; 4]| (0..4).map(|c| (t[c] - mean[c]) * axis[c]).sum::<f32>();
+    let (low, high) = texels
+        .iter()
+        .map(project)
+        .fold((0f32, 0f32), |(low, high), t| (low.min(t), high.max(t)));
+    let endpoint = |t: f32| [0, 1, 2, 3].map(|c| (mean[c] + axis[c] * t).clamp(0.0, 255.0));
+
+    let (mut q0, mut p0) = quantize_endpoint(endpoint(low));
+    let (mut q1, mut p1) = quantize_endpoint(endpoint(high));
+    let e0 = q0.map(|q| q << 1 | p0);
+    let e1 = q1.map(|q| q << 1 | p1);
+
+    let mut indices = [0u8; 16];
+    for (index, p) in indices.iter_mut().zip(pixels.iter()) {
+        let error = |i: usize| {
+            (0..4)
+                .map(|c| {
+                    let d = interpolate(e0[c], e1[c], WEIGHTS_4[i]) as i32 - p[c] as i32;
+                    d * d
+                })
+                .sum::<i32>()
+        };
+        *index = (0..16).min_by_key(|&i| error(i)).unwrap_or(0) as u8;
+    }
+
+    /* The anchor index has an implied top bit of 0, swap the endpoints if it is set */
+    if indices[0] >= 8 {
+        std::mem::swap(&mut q0, &mut q1);
+        std::mem::swap(&mut p0, &mut p1);
+        indices = indices.map(|i| 15 - i);
+    }
+
+    let mut block = BitWriter {
+        value: 0,
+        offset: 0,
+    };
+    block.write(7, 1 << 6);
+    for c in 0..4 {
+        block.write(7, q0[c] as u32);
+        block.write(7, q1[c] as u32);
+    }
+    block.write(1, p0 as u32);
+    block.write(1, p1 as u32);
+    block.write(3, indices[0] as u32);
+    for &index in indices[1..].iter() {
+        block.write(4, index as u32);
+    }
+
+    block.value.to_le_bytes()
+}
+
+/* Encode straight RGBA8 pixels as a BC7 texture with its size header */
+pub(super) fn encode(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    let blocks_x = (width as usize).div_ceil(4);
+    let blocks_y = (height as usize).div_ceil(4);
+
+    let mut data = Vec::with_capacity(HEADER_SIZE + blocks_x * blocks_y * BLOCK_SIZE);
+    data.extend_from_slice(&width.to_be_bytes());
+    data.extend_from_slice(&height.to_be_bytes());
+
+    let stride = width as usize * 4;
+    for by in 0..blocks_y {
+        for bx in 0..blocks_x {
+            /* Pixels past the edge repeat the last row or column */
+            let block = std::array::from_fn(|pixel| {
+                let x = (bx * 4 + pixel % 4).min(width as usize - 1);
+                let y = (by * 4 + pixel / 4).min(height as usize - 1);
+                let offset = y * stride + x * 4;
+                [
+                    pixels[offset],
+                    pixels[offset + 1],
+                    pixels[offset + 2],
+                    pixels[offset + 3],
+                ]
+            });
+            data.extend_from_slice(&encode_block(&block));
+        }
+    }
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anchors_match_partitions() {
+        for partition in 0..64 {
+            assert_eq!(PARTITIONS_2[partition][0], 0);
+            assert_eq!(PARTITIONS_2[partition][ANCHORS_2[partition] as usize], 1);
+
+            assert_eq!(PARTITIONS_3[partition][0], 0);
+            assert_eq!(PARTITIONS_3[partition][ANCHORS_3A[partition] as usize], 1);
+            assert_eq!(PARTITIONS_3[partition][ANCHORS_3B[partition] as usize], 2);
+        }
+    }
+
+    #[test]
+    fn test_mode_6() {
+        /* Endpoint 0 is (0x20, 0x40, 0x60, 0xFE), endpoint 1 is (0xA1, 0xC1, 0xE1, 0xFF) */
+        let mut block = BitWriter {
+            value: 0,
+            offset: 0,
+        };
+        block.write(7, 1 << 6);
+        for (e0, e1) in [(0x10, 0x50), (0x20, 0x60), (0x30, 0x70), (0x7F, 0x7F)] {
+            block.write(7, e0);
+            block.write(7, e1);
+        }
+        block.write(1, 0);
+        block.write(1, 1);
+        /* Pixel 0 uses endpoint 0, every other pixel endpoint 1 */
+        block.write(3, 0);
+        for _ in 1..16 {
+            block.write(4, 15);
+        }
+        assert_eq!(block.offset, 128);
+
+        let pixels = decode_block(&block.value.to_le_bytes());
+        assert_eq!(pixels[0], [0x20, 0x40, 0x60, 0xFE]);
+        assert!(pixels[1..].iter().all(|&p| p == [0xA1, 0xC1, 0xE1, 0xFF]));
+    }
+
+    #[test]
+    fn test_decode_size() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&5u32.to_be_bytes());
+        data.extend_from_slice(&1u32.to_be_bytes());
+        assert!(decode(&data).is_err());
+
+        /* Two reserved mode blocks cover a 5x1 image */
+        data.extend_from_slice(&[0; BLOCK_SIZE * 2]);
+        let (width, height, pixels) = decode(&data).unwrap();
+        assert_eq!((width, height), (5, 1));
+        assert_eq!(pixels, vec![0; 5 * 4]);
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        /* Mode 6 fits a line through each block, so use a diagonal gradient */
+        let mut pixels = Vec::new();
+        for y in 0..6u32 {
+            for x in 0..7u32 {
+                let t = (x + y) * 20;
+                pixels.extend_from_slice(&[t as u8, 0xFF - t as u8, 0x80, 0xFF - t as u8 / 2]);
+            }
+        }
+
+        let data = encode(7, 6, &pixels);
+        assert_eq!(data.len(), HEADER_SIZE + 2 * 2 * BLOCK_SIZE);
+
+        let (width, height, decoded) = decode(&data).unwrap();
+        assert_eq!((width, height), (7, 6));
+        for (a, b) in pixels.iter().zip(decoded.iter()) {
+            assert!((*a as i32 - *b as i32).abs() <= 12, "{} vs {}", a, b);
+        }
+    }
+}
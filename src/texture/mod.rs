@@ -0,0 +1,467 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+/*!
+    Decoding of the textures stored in INP/INX containers into RGBA8 images, without a GPU
+    or the Inochi2D runtime.
+
+    BC7 textures use a size header of this crate's own, see `TextureEncoding::Bc7`.
+*/
+
+pub mod bc7;
+mod optimize;
+pub(crate) mod tga;
+
+pub use optimize::{optimize, OptimizeOptions, OptimizeReport, TextureStats};
+
+use std::error::Error;
+use std::fmt;
+
+use crate::format::{LoadLimits, Texture, TextureEncoding};
+
+/// How the colour channels of an `Image` relate to its alpha channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// Colour channels are independent of alpha.
+    Straight,
+    /// Colour channels have been multiplied by alpha.
+    Premultiplied,
+}
+
+/// Errors produced while decoding a texture.
+#[derive(Debug)]
+pub enum TextureError {
+    /// The texture slot is empty.
+    Empty,
+    /// The PNG data could not be decoded.
+    Png(png::DecodingError),
+    /// The image could not be encoded as a PNG.
+    PngEncode(png::EncodingError),
+    /// The TGA data is malformed or uses a feature that is not supported.
+    Tga(String),
+    /// The BC7 data is malformed.
+    Bc7(String),
+    /// The image is larger than `LoadLimits::default().max_texture_dimension` allows to decode.
+    TooLarge { width: u32, height: u32 },
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureError::Empty => write!(f, "Texture slot is empty"),
+            TextureError::Png(err) => write!(f, "Invalid PNG texture: {}", err),
+            TextureError::PngEncode(err) => write!(f, "Unable to encode PNG texture: {}", err),
+            TextureError::Tga(message) => write!(f, "Invalid TGA texture: {}", message),
+            TextureError::Bc7(message) => write!(f, "Invalid BC7 texture: {}", message),
+            TextureError::TooLarge { width, height } => {
+                write!(f, "Texture is too large to decode ({}x{})", width, height)
+            }
+        }
+    }
+}
+
+impl Error for TextureError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TextureError::Png(err) => Some(err),
+            TextureError::PngEncode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<png::DecodingError> for TextureError {
+    fn from(err: png::DecodingError) -> Self {
+        TextureError::Png(err)
+    }
+}
+
+impl From<png::EncodingError> for TextureError {
+    fn from(err: png::EncodingError) -> Self {
+        TextureError::PngEncode(err)
+    }
+}
+
+/// A decoded RGBA8 image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA8 pixels, row by row from the top.
+    pub pixels: Vec<u8>,
+    pub alpha: AlphaMode,
+}
+
+impl Image {
+    /// Get the RGBA value of the pixel at (`x`, `y`).
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let offset = (y as usize * self.width as usize + x as usize) * 4;
+        let p = &self.pixels[offset..offset + 4];
+
+        [p[0], p[1], p[2], p[3]]
+    }
+
+    /// Convert the image to `alpha`, doing nothing if it already uses it.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let image = texture::decode(&container.textures[0])?;
+    ///
+    /// upload_to_gpu(image.into_alpha(AlphaMode::Premultiplied));
+    /// ~~~
+    ///
+    pub fn into_alpha(mut self, alpha: AlphaMode) -> Self {
+        if self.alpha == alpha {
+            return self;
+        }
+
+        for p in self.pixels.chunks_exact_mut(4) {
+            let a = p[3] as u32;
+            for c in p[..3].iter_mut() {
+                *c = match alpha {
+                    AlphaMode::Premultiplied => ((*c as u32 * a + 127) / 255) as u8,
+                    AlphaMode::Straight if a == 0 => 0,
+                    AlphaMode::Straight => ((*c as u32 * 255 + a / 2) / a).min(255) as u8,
+                };
+            }
+        }
+        self.alpha = alpha;
+
+        self
+    }
+
+    /// Resize the image with a box filter, averaging in premultiplied alpha.
+    ///
+    /// # Example
+    ///
+    /// ~~~no_run
+    /// let image = texture::decode(&container.textures[0])?;
+    /// let half = image.resize(image.width / 2, image.height / 2);
+    /// ~~~
+    ///
+    pub fn resize(&self, width: u32, height: u32) -> Image {
+        let alpha = self.alpha;
+        let source = self.clone().into_alpha(AlphaMode::Premultiplied);
+        let (width, height) = (width.max(1), height.max(1));
+
+        /* Source pixels covered by destination pixel `i` of `n`, along an axis of `len` */
+        let span = |i: u32, n: u32, len: u32| {
+            let start = (i as u64 * len as u64 / n as u64) as u32;
+            let end = ((i as u64 + 1) * len as u64).div_ceil(n as u64) as u32;
+            start..end.max(start + 1).min(len)
+        };
+
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+        for y in 0..height {
+            let rows = span(y, height, source.height);
+            for x in 0..width {
+                let columns = span(x, width, source.width);
+
+                let mut sum = [0u32; 4];
+                let mut count = 0;
+                for sy in rows.clone() {
+                    for sx in columns.clone() {
+                        let p = source.pixel(sx, sy);
+                        for c in 0..4 {
+                            sum[c] += p[c] as u32;
+                        }
+                        count += 1;
+                    }
+                }
+                pixels.extend(sum.map(|c| ((c + count / 2) / count.max(1)) as u8));
+            }
+        }
+
+        Image {
+            width,
+            height,
+            pixels,
+            alpha: AlphaMode::Premultiplied,
+        }
+        .into_alpha(alpha)
+    }
+}
+
+fn decode_png(data: &[u8]) -> Result<(u32, u32, Vec<u8>), TextureError> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+
+    /* The output buffer is ours to allocate and png doesn't limit it, so check the header first */
+    let (width, height) = reader.info().size();
+    if width.max(height) > LoadLimits::default().max_texture_dimension {
+        return Err(TextureError::TooLarge { width, height });
+    }
+
+    let mut buffer = vec![0u8; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    buffer.truncate(info.buffer_size());
+
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => buffer,
+        png::ColorType::Rgb => buffer
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 0xFF])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buffer
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Grayscale => buffer.iter().flat_map(|&p| [p, p, p, 0xFF]).collect(),
+        /* `normalize_to_color8` expands palettes, so this never happens */
+        png::ColorType::Indexed => unreachable!("PNG palette was not expanded"),
+    };
+
+    Ok((info.width, info.height, pixels))
+}
+
+/// Get the dimensions of a texture without decoding it.
+pub fn dimensions(texture: &Texture) -> Result<(u32, u32), TextureError> {
+    encoded_dimensions(texture.encoding, &texture.data)
+}
+
+/* As `dimensions`, for texture data that is still borrowed from a container */
+pub(crate) fn encoded_dimensions(
+    encoding: TextureEncoding,
+    data: &[u8],
+) -> Result<(u32, u32), TextureError> {
+    if data.is_empty() {
+        return Err(TextureError::Empty);
+    }
+
+    match encoding {
+        TextureEncoding::Png => {
+            let reader = png::Decoder::new(data).read_info()?;
+            let info = reader.info();
+            Ok((info.width, info.height))
+        }
+        TextureEncoding::Tga => match data.get(12..16) {
+            Some(size) => Ok((
+                u16::from_le_bytes([size[0], size[1]]) as u32,
+                u16::from_le_bytes([size[2], size[3]]) as u32,
+            )),
+            None => Err(TextureError::Tga("truncated image".into())),
+        },
+        TextureEncoding::Bc7 => match data.get(..bc7::HEADER_SIZE) {
+            Some(size) => Ok((
+                u32::from_be_bytes([size[0], size[1], size[2], size[3]]),
+                u32::from_be_bytes([size[4], size[5], size[6], size[7]]),
+            )),
+            None => Err(TextureError::Bc7("missing size header".into())),
+        },
+    }
+}
+
+/// Get the number of bytes of GPU memory a texture takes up once uploaded.
+///
+/// PNG and TGA textures are uploaded as RGBA8, BC7 textures stay compressed.
+///
+pub fn memory_size(texture: &Texture) -> Result<usize, TextureError> {
+    let (width, height) = dimensions(texture)?;
+
+    Ok(match texture.encoding {
+        TextureEncoding::Bc7 => {
+            (width as usize).div_ceil(4) * (height as usize).div_ceil(4) * bc7::BLOCK_SIZE
+        }
+        _ => width as usize * height as usize * 4,
+    })
+}
+
+/// Encode an image as a texture for a container.
+///
+/// # Example
+///
+/// ~~~no_run
+/// let image = texture::decode(&container.textures[0])?;
+///
+/// container.textures[0] = texture::encode(&image, TextureEncoding::Bc7)?;
+/// ~~~
+///
+/// # Returns
+///
+/// - The encoded texture.
+/// - Otherwise a `TextureError` describing the error.
+///
+pub fn encode(image: &Image, encoding: TextureEncoding) -> Result<Texture, TextureError> {
+    let straight = image.clone().into_alpha(AlphaMode::Straight);
+    let (width, height) = (straight.width, straight.height);
+
+    let data = match encoding {
+        TextureEncoding::Png => {
+            let mut data = Vec::new();
+            let mut encoder = png::Encoder::new(&mut data, width, height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.write_header()?.write_image_data(&straight.pixels)?;
+            data
+        }
+        TextureEncoding::Tga => tga::encode(width, height, &straight.pixels)?,
+        TextureEncoding::Bc7 => bc7::encode(width, height, &straight.pixels),
+    };
+
+    Ok(Texture { encoding, data })
+}
+
+/// Decode a texture from a container into a straight alpha RGBA8 image.
+///
+/// # Example
+///
+/// ~~~no_run
+/// let container = Container::open("./puppets/Ada.inx")?;
+///
+/// for texture in container.textures.iter().filter(|texture| !texture.data.is_empty()) {
+///     let image = texture::decode(texture)?;
+///     println!("{}x{}", image.width, image.height);
+/// }
+/// ~~~
+///
+/// # Returns
+///
+/// - The decoded image.
+/// - Otherwise a `TextureError` describing the error.
+///
+pub fn decode(texture: &Texture) -> Result<Image, TextureError> {
+    if texture.data.is_empty() {
+        return Err(TextureError::Empty);
+    }
+
+    let (width, height, pixels) = match texture.encoding {
+        TextureEncoding::Png => decode_png(&texture.data)?,
+        TextureEncoding::Tga => tga::decode(&texture.data)?,
+        TextureEncoding::Bc7 => bc7::decode(&texture.data)?,
+    };
+
+    Ok(Image {
+        width,
+        height,
+        pixels,
+        alpha: AlphaMode::Straight,
+    })
+}
+
+/// Decode a texture from a container into a premultiplied alpha RGBA8 image.
+pub fn decode_premultiplied(texture: &Texture) -> Result<Image, TextureError> {
+    Ok(decode(texture)?.into_alpha(AlphaMode::Premultiplied))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alpha_conversion() {
+        let image = Image {
+            width: 3,
+            height: 1,
+            pixels: vec![255, 128, 0, 128, 10, 20, 30, 0, 1, 2, 3, 255],
+            alpha: AlphaMode::Straight,
+        };
+
+        let premultiplied = image.clone().into_alpha(AlphaMode::Premultiplied);
+        assert_eq!(
+            premultiplied.pixels,
+            vec![128, 64, 0, 128, 0, 0, 0, 0, 1, 2, 3, 255]
+        );
+
+        let straight = premultiplied.into_alpha(AlphaMode::Straight);
+        assert_eq!(straight.pixel(0, 0), [255, 128, 0, 128]);
+        assert_eq!(straight.pixel(1, 0), [0, 0, 0, 0]);
+        assert_eq!(straight.pixel(2, 0), [1, 2, 3, 255]);
+    }
+
+    #[test]
+    fn test_decode_tga_texture() {
+        let texture = Texture {
+            encoding: TextureEncoding::Tga,
+            data: tga::tests::tga(1, 1, &[[200, 100, 50, 0x80]]),
+        };
+
+        assert_eq!(decode(&texture).unwrap().pixel(0, 0), [200, 100, 50, 0x80]);
+        assert_eq!(
+            decode_premultiplied(&texture).unwrap().pixel(0, 0),
+            [100, 50, 25, 0x80]
+        );
+        assert!(matches!(
+            decode(&Texture {
+                encoding: TextureEncoding::Png,
+                data: Vec::new()
+            }),
+            Err(TextureError::Empty)
+        ));
+    }
+
+    #[test]
+    fn test_encode_png() {
+        let image = Image {
+            width: 2,
+            height: 1,
+            pixels: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            alpha: AlphaMode::Straight,
+        };
+
+        let texture = encode(&image, TextureEncoding::Png).unwrap();
+        assert_eq!(dimensions(&texture).unwrap(), (2, 1));
+        assert_eq!(memory_size(&texture).unwrap(), 8);
+        assert_eq!(decode(&texture).unwrap(), image);
+    }
+
+    #[test]
+    fn test_png_too_large() {
+        let image = Image {
+            width: 1,
+            height: 1,
+            pixels: vec![0; 4],
+            alpha: AlphaMode::Straight,
+        };
+        let mut texture = encode(&image, TextureEncoding::Png).unwrap();
+
+        /* Claim 65535x65535 in the IHDR chunk, fixing up its CRC */
+        texture.data[16..24].copy_from_slice(&[0, 0, 0xFF, 0xFF, 0, 0, 0xFF, 0xFF]);
+        let crc = crc32(&texture.data[12..29]);
+        texture.data[29..33].copy_from_slice(&crc.to_be_bytes());
+
+        assert_eq!(dimensions(&texture).unwrap(), (65535, 65535));
+        assert!(matches!(
+            decode(&texture),
+            Err(TextureError::TooLarge {
+                width: 65535,
+                height: 65535
+            })
+        ));
+    }
+
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in data {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+            }
+        }
+
+        !crc
+    }
+
+    #[test]
+    fn test_resize() {
+        let image = Image {
+            width: 2,
+            height: 2,
+            pixels: vec![
+                255, 0, 0, 255, 0, 0, 0, 0, //
+                255, 0, 0, 255, 0, 0, 0, 0,
+            ],
+            alpha: AlphaMode::Straight,
+        };
+
+        /* The transparent pixels don't darken the result */
+        let resized = image.resize(1, 1);
+        assert_eq!(resized.pixels, vec![255, 0, 0, 128]);
+        assert_eq!(image.resize(4, 4).pixel(3, 3), [0, 0, 0, 0]);
+    }
+}
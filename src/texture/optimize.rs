@@ -0,0 +1,377 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+use std::collections::BTreeSet;
+
+use serde_json::Value;
+
+#[cfg(feature = "logging")]
+use tracing::warn;
+
+use super::{decode, dimensions, encode, memory_size};
+use crate::format::model::{Node, NodeKind, NO_TEXTURE, NO_THUMBNAIL};
+use crate::format::{Container, FormatError, Texture, TextureEncoding};
+
+/// What `optimize` does to a container's textures.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct OptimizeOptions {
+    /// Downscale textures whose width or height exceeds this, keeping their aspect ratio.
+    pub max_dimension: Option<u32>,
+    /// Re-encode every texture with this encoding.
+    pub encoding: Option<TextureEncoding>,
+    /// Allow `encoding` to be `TextureEncoding::Bc7`.
+    ///
+    /// BC7 textures are written with a size header of this crate's own, which released
+    /// Inochi2D runtimes are not known to load, see `TextureEncoding::Bc7`. Only enable this
+    /// for runtimes you have checked.
+    ///
+    pub allow_bc7: bool,
+    /// Remove textures no node or the thumbnail refers to, renumbering the rest.
+    ///
+    /// This rewrites the puppet JSON through the typed model, see `Container::set_puppet`.
+    ///
+    pub strip_unused: bool,
+}
+
+/// Size totals of a container's texture section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TextureStats {
+    /// Number of non-empty textures.
+    pub count: usize,
+    /// Bytes the encoded textures take up in the file.
+    pub file_bytes: usize,
+    /// Bytes of GPU memory the textures take up once uploaded, see `texture::memory_size`.
+    pub memory_bytes: usize,
+}
+
+impl TextureStats {
+    fn of(textures: &[Texture]) -> Result<Self, FormatError> {
+        let mut stats = TextureStats::default();
+        for (index, texture) in textures.iter().enumerate() {
+            if texture.data.is_empty() {
+                continue;
+            }
+
+            stats.count += 1;
+            stats.file_bytes += texture.data.len();
+            stats.memory_bytes +=
+                memory_size(texture).map_err(|error| FormatError::Texture { index, error })?;
+        }
+
+        Ok(stats)
+    }
+}
+
+/// The outcome of `optimize`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptimizeReport {
+    pub before: TextureStats,
+    pub after: TextureStats,
+    /// The new index of each original texture, `None` for removed textures.
+    pub remap: Vec<Option<u32>>,
+}
+
+/* Texture slots of a part, see `Node::texture_slots` */
+fn node_textures(node: &mut Node) -> Vec<&mut u32> {
+    match &mut node.kind {
+        NodeKind::Part(part) => part.textures.iter_mut().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/* Texture slots in the `textures` array of any other kind of node */
+fn extra_node_textures(node: &mut Node) -> Vec<&mut Value> {
+    match node.extra.get_mut("textures") {
+        Some(Value::Array(slots)) => slots.iter_mut().collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn slot(value: &Value) -> Option<u32> {
+    value.as_u64().and_then(|slot| u32::try_from(slot).ok())
+}
+
+/* Remap every texture reference in the tree, unknown slots become `NO_TEXTURE` */
+fn remap_nodes(root: &mut Node, remap: &[Option<u32>]) {
+    let new_slot = |slot: u32| {
+        remap
+            .get(slot as usize)
+            .copied()
+            .flatten()
+            .unwrap_or(NO_TEXTURE)
+    };
+
+    root.visit_mut(&mut |node| {
+        for slot in node_textures(node) {
+            if *slot != NO_TEXTURE {
+                *slot = new_slot(*slot);
+            }
+        }
+        for value in extra_node_textures(node) {
+            if let Some(old) = slot(value).filter(|&old| old != NO_TEXTURE) {
+                *value = Value::from(new_slot(old));
+            }
+        }
+    });
+}
+
+/// Rewrite the texture section of a container to use less memory.
+///
+/// Textures are referred to by slot index and sampled with normalized UVs, so downscaling
+/// or re-encoding needs no change to meshes. When unused textures are stripped the slot
+/// references of every part and the thumbnail are renumbered.
+///
+/// BC7 textures are smaller on disk and in VRAM, but released Inochi2D runtimes are not
+/// known to load them, so `Inochi2DPuppet::from_bytes` may reject the result. Re-encoding
+/// as BC7 fails with `FormatError::EncodingNotAllowed` unless `OptimizeOptions::allow_bc7`
+/// is set.
+///
+/// # Example
+///
+/// ~~~no_run
+/// let mut container = Container::open("./puppets/Ada.inx")?;
+///
+/// let report = texture::optimize(&mut container, &OptimizeOptions {
+///     max_dimension: Some(4096),
+///     ..OptimizeOptions::default()
+/// })?;
+/// println!("{} -> {} bytes of VRAM", report.before.memory_bytes, report.after.memory_bytes);
+///
+/// let puppet = Inochi2DPuppet::from_bytes(&container.to_bytes()?, None)?;
+/// ~~~
+///
+/// # Returns
+///
+/// - The texture sizes before and after, and where each texture went.
+/// - Otherwise a `FormatError` describing the error, the container is left unchanged.
+///
+pub fn optimize(
+    container: &mut Container,
+    options: &OptimizeOptions,
+) -> Result<OptimizeReport, FormatError> {
+    match options.encoding {
+        Some(TextureEncoding::Bc7) if !options.allow_bc7 => {
+            return Err(FormatError::EncodingNotAllowed(TextureEncoding::Bc7));
+        }
+        #[cfg(feature = "logging")]
+        Some(TextureEncoding::Bc7) => {
+            warn!("Re-encoding textures as BC7, the Inochi2D runtime may not load them")
+        }
+        _ => (),
+    }
+
+    let before = TextureStats::of(&container.textures)?;
+    let mut puppet = container.puppet()?;
+
+    let mut textures = container.textures.clone();
+    let mut remap: Vec<Option<u32>> = (0..textures.len() as u32).map(Some).collect();
+
+    if options.strip_unused {
+        let mut used = BTreeSet::new();
+        used.insert(puppet.meta.thumbnail_id);
+        used.extend(puppet.nodes.iter().flat_map(|node| node.texture_slots()));
+
+        let mut kept = Vec::new();
+        for (index, texture) in textures.into_iter().enumerate() {
+            if used.contains(&(index as u32)) {
+                remap[index] = Some(kept.len() as u32);
+                kept.push(texture);
+            } else {
+                remap[index] = None;
+            }
+        }
+        textures = kept;
+
+        remap_nodes(&mut puppet.nodes, &remap);
+        if puppet.meta.thumbnail_id != NO_THUMBNAIL {
+            puppet.meta.thumbnail_id = remap
+                .get(puppet.meta.thumbnail_id as usize)
+                .copied()
+                .flatten()
+                .unwrap_or(NO_THUMBNAIL);
+        }
+    }
+
+    for (index, texture) in textures.iter_mut().enumerate() {
+        if texture.data.is_empty() {
+            continue;
+        }
+        let error = |error| FormatError::Texture { index, error };
+
+        let (width, height) = dimensions(texture).map_err(error)?;
+        let scale = match options.max_dimension {
+            Some(max) if width.max(height) > max => max.max(1) as f64 / width.max(height) as f64,
+            _ => 1.0,
+        };
+        let encoding = options.encoding.unwrap_or(texture.encoding);
+        if scale == 1.0 && encoding == texture.encoding {
+            continue;
+        }
+
+        let mut image = decode(texture).map_err(error)?;
+        if scale < 1.0 {
+            image = image.resize(
+                (width as f64 * scale).round() as u32,
+                (height as f64 * scale).round() as u32,
+            );
+        }
+        *texture = encode(&image, encoding).map_err(error)?;
+    }
+
+    let after = TextureStats::of(&textures)?;
+    if options.strip_unused {
+        container.set_puppet(&puppet)?;
+    }
+    container.textures = textures;
+
+    Ok(OptimizeReport {
+        before,
+        after,
+        remap,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Issue;
+    use crate::texture::{AlphaMode, Image};
+
+    fn texture(size: u32) -> Texture {
+        let image = Image {
+            width: size,
+            height: size,
+            pixels: vec![0x80; size as usize * size as usize * 4],
+            alpha: AlphaMode::Straight,
+        };
+
+        encode(&image, TextureEncoding::Png).unwrap()
+    }
+
+    #[test]
+    fn test_optimize() {
+        let json = r#"{
+            "meta": { "version": "1.0-alpha", "thumbnailId": 2 },
+            "nodes": {
+                "uuid": 0, "name": "Root", "type": "Node", "enabled": true, "zsort": 0,
+                "transform": { "trans": [0, 0, 0], "rot": [0, 0, 0], "scale": [1, 1] },
+                "lockToRoot": false,
+                "children": [{
+                    "uuid": 1, "name": "Body", "type": "Part", "enabled": true, "zsort": 0,
+                    "transform": { "trans": [0, 0, 0], "rot": [0, 0, 0], "scale": [1, 1] },
+                    "lockToRoot": false,
+                    "mesh": { "verts": [], "uvs": [], "indices": [] },
+                    "textures": [3, 4294967295, 4294967295],
+                    "blend_mode": "Normal", "tint": [1, 1, 1], "mask_threshold": 0.5,
+                    "opacity": 1
+                }]
+            },
+            "param": []
+        }"#;
+        let mut container = Container {
+            json: json.into(),
+            textures: vec![texture(4), texture(4), texture(8), texture(16)],
+            vendor: Vec::new(),
+        };
+
+        let report = optimize(
+            &mut container,
+            &OptimizeOptions {
+                max_dimension: Some(8),
+                encoding: Some(TextureEncoding::Bc7),
+                allow_bc7: true,
+                strip_unused: true,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(report.remap, vec![None, None, Some(0), Some(1)]);
+        assert_eq!(report.before.count, 4);
+        assert_eq!(report.before.memory_bytes, (16 + 16 + 64 + 256) * 4);
+        assert_eq!(report.after.count, 2);
+        assert_eq!(report.after.memory_bytes, (4 + 4) * 16);
+
+        let puppet = container.puppet().unwrap();
+        assert_eq!(puppet.meta.thumbnail_id, 0);
+        match &puppet.nodes.children[0].kind {
+            NodeKind::Part(part) => assert_eq!(part.textures[0], 1),
+            _ => panic!("expected a part"),
+        }
+        assert!(container
+            .textures
+            .iter()
+            .all(|texture| texture.encoding == TextureEncoding::Bc7));
+        assert_eq!(dimensions(&container.textures[1]).unwrap(), (8, 8));
+    }
+
+    #[test]
+    fn test_keeps_referenced() {
+        /* Every kind of node can refer to textures, not only parts */
+        let json = r#"{
+            "meta": { "version": "1.0-alpha", "thumbnailId": 4294967295 },
+            "nodes": {
+                "uuid": 0, "name": "Root", "type": "Node",
+                "children": [
+                    {
+                        "uuid": 1, "name": "Body", "type": "Part",
+                        "mesh": { "verts": [], "uvs": [], "indices": [] },
+                        "textures": [1, 4294967295, 4294967295]
+                    },
+                    { "uuid": 2, "name": "Group", "type": "Composite", "textures": [2] },
+                    {
+                        "uuid": 3, "name": "Clip", "type": "Mask",
+                        "mesh": { "verts": [], "uvs": [], "indices": [] }, "textures": [4]
+                    },
+                    { "uuid": 4, "name": "Blink", "type": "AnimatedPart", "textures": [5] }
+                ]
+            },
+            "param": []
+        }"#;
+        let mut container = Container {
+            json: json.into(),
+            textures: (0..6).map(|_| texture(4)).collect(),
+            vendor: Vec::new(),
+        };
+        let referenced = |container: &Container| {
+            let puppet = container.puppet().unwrap();
+            let mut slots: Vec<u32> = puppet
+                .nodes
+                .iter()
+                .flat_map(|node| node.texture_slots())
+                .collect();
+            slots.sort();
+            slots
+        };
+        assert_eq!(referenced(&container), vec![1, 2, 4, 5]);
+
+        let strip = OptimizeOptions {
+            strip_unused: true,
+            ..OptimizeOptions::default()
+        };
+        let report = optimize(&mut container, &strip).unwrap();
+        assert_eq!(
+            report.remap,
+            vec![None, Some(0), Some(1), None, Some(2), Some(3)]
+        );
+        assert_eq!(referenced(&container), vec![0, 1, 2, 3]);
+        assert!(!container
+            .validate()
+            .unwrap()
+            .iter()
+            .any(|diagnostic| matches!(diagnostic.issue, Issue::MissingTexture { .. })));
+
+        /* BC7 output has to be asked for explicitly */
+        let bc7 = OptimizeOptions {
+            encoding: Some(TextureEncoding::Bc7),
+            ..OptimizeOptions::default()
+        };
+        assert!(matches!(
+            optimize(&mut container, &bc7),
+            Err(FormatError::EncodingNotAllowed(TextureEncoding::Bc7))
+        ));
+    }
+}
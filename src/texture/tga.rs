@@ -0,0 +1,181 @@
+/*
+    Copyright © 2022, Inochi2D Project
+    Distributed under the 2-Clause BSD License, see LICENSE file.
+
+    Authors: Aki "lethalbit" Van Ness
+*/
+
+use super::TextureError;
+
+/* Decode uncompressed and RLE true-colour or greyscale TGA images to straight RGBA8 */
+pub(super) fn decode(data: &[u8]) -> Result<(u32, u32, Vec<u8>), TextureError> {
+    let truncated = || TextureError::Tga("truncated image".into());
+    if data.len() < 18 {
+        return Err(truncated());
+    }
+
+    let id_len = data[0] as usize;
+    let colormap = data[1];
+    let image_type = data[2];
+    let width = u16::from_le_bytes([data[12], data[13]]) as u32;
+    let height = u16::from_le_bytes([data[14], data[15]]) as u32;
+    let depth = data[16];
+    let top_down = data[17] & 0x20 != 0;
+
+    let (rle, grey) = match image_type {
+        2 => (false, false),
+        3 => (false, true),
+        10 => (true, false),
+        11 => (true, true),
+        _ => {
+            return Err(TextureError::Tga(format!(
+                "unsupported image type {}",
+                image_type
+            )))
+        }
+    };
+    let bytes_per_pixel = match (grey, depth) {
+        (false, 24) => 3,
+        (false, 32) => 4,
+        (true, 8) => 1,
+        _ => return Err(TextureError::Tga(format!("unsupported depth {}", depth))),
+    };
+    if colormap != 0 {
+        return Err(TextureError::Tga(
+            "colour-mapped images are unsupported".into(),
+        ));
+    }
+
+    let to_rgba = |p: &[u8]| match p.len() {
+        1 => [p[0], p[0], p[0], 0xFF],
+        3 => [p[2], p[1], p[0], 0xFF],
+        _ => [p[2], p[1], p[0], p[3]],
+    };
+
+    let count = width as usize * height as usize;
+    let mut body = data.get(18 + id_len..).ok_or_else(truncated)?;
+    /* Don't trust the header for allocation, check the body can hold that many pixels first */
+    let max_pixels = if rle {
+        /* Every packet takes at least a byte and a pixel, and holds at most 128 pixels */
+        body.len().div_ceil(1 + bytes_per_pixel) * 128
+    } else {
+        body.len() / bytes_per_pixel
+    };
+    if count > max_pixels {
+        return Err(truncated());
+    }
+    let mut rows = Vec::with_capacity(count.min(body.len()) * 4);
+    while rows.len() < count * 4 {
+        if !rle {
+            let pixel = body.get(..bytes_per_pixel).ok_or_else(truncated)?;
+            rows.extend_from_slice(&to_rgba(pixel));
+            body = &body[bytes_per_pixel..];
+            continue;
+        }
+
+        let (&packet, rest) = body.split_first().ok_or_else(truncated)?;
+        let run = (packet & 0x7F) as usize + 1;
+        if packet & 0x80 != 0 {
+            let pixel = rest.get(..bytes_per_pixel).ok_or_else(truncated)?;
+            for _ in 0..run {
+                rows.extend_from_slice(&to_rgba(pixel));
+            }
+            body = &rest[bytes_per_pixel..];
+        } else {
+            let raw = rest.get(..run * bytes_per_pixel).ok_or_else(truncated)?;
+            rows.extend(raw.chunks_exact(bytes_per_pixel).flat_map(to_rgba));
+            body = &rest[run * bytes_per_pixel..];
+        }
+    }
+    rows.truncate(count * 4);
+
+    /* TGA rows are stored bottom to top unless the descriptor says otherwise */
+    let pixels = if top_down || width == 0 {
+        rows
+    } else {
+        rows.chunks_exact(width as usize * 4)
+            .rev()
+            .flatten()
+            .copied()
+            .collect()
+    };
+
+    Ok((width, height, pixels))
+}
+
+/* Encode straight RGBA8 pixels as an uncompressed, top-down 32-bit TGA image */
+pub(super) fn encode(width: u32, height: u32, pixels: &[u8]) -> Result<Vec<u8>, TextureError> {
+    let (width, height) = match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => {
+            return Err(TextureError::Tga(format!(
+                "{}x{} is too large",
+                width, height
+            )))
+        }
+    };
+
+    let mut data = vec![0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    data.extend_from_slice(&width.to_le_bytes());
+    data.extend_from_slice(&height.to_le_bytes());
+    data.extend_from_slice(&[32, 0x28]);
+    for p in pixels.chunks_exact(4) {
+        data.extend_from_slice(&[p[2], p[1], p[0], p[3]]);
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn tga(width: u16, height: u16, pixels: &[[u8; 4]]) -> Vec<u8> {
+        let mut data = vec![0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend_from_slice(&width.to_le_bytes());
+        data.extend_from_slice(&height.to_le_bytes());
+        data.extend_from_slice(&[32, 0x20]);
+        for p in pixels {
+            data.extend_from_slice(&[p[2], p[1], p[0], p[3]]);
+        }
+        data
+    }
+
+    #[test]
+    fn test_oversized_header() {
+        /* A bare header claiming 65535x65535 must fail without allocating the image */
+        let raw = tga(u16::MAX, u16::MAX, &[]);
+        assert!(matches!(decode(&raw), Err(TextureError::Tga(_))));
+
+        let mut rle = raw.clone();
+        rle[2] = 10;
+        rle.extend_from_slice(&[0xFF, 0, 0, 0, 0xFF]);
+        assert!(matches!(decode(&rle), Err(TextureError::Tga(_))));
+    }
+
+    #[test]
+    fn test_decode() {
+        let red = [0xFF, 0, 0, 0xFF];
+        let blue = [0, 0, 0xFF, 0x80];
+
+        let (width, height, pixels) = decode(&tga(2, 1, &[red, blue])).unwrap();
+        assert_eq!((width, height), (2, 1));
+        assert_eq!(pixels, [red, blue].concat());
+
+        /* A single RLE packet repeating `red` twice */
+        let mut rle = tga(2, 1, &[]);
+        rle[2] = 10;
+        rle.extend_from_slice(&[0x81, 0, 0, 0xFF, 0xFF]);
+        assert_eq!(decode(&rle).unwrap().2, [red, red].concat());
+
+        /* Bottom-up rows are flipped */
+        let mut bottom_up = tga(1, 2, &[red, blue]);
+        bottom_up[17] = 0;
+        assert_eq!(decode(&bottom_up).unwrap().2, [blue, red].concat());
+
+        assert!(decode(&tga(2, 1, &[red])).is_err());
+
+        let encoded = encode(2, 1, &[red, blue].concat()).unwrap();
+        assert_eq!(decode(&encoded).unwrap().2, [red, blue].concat());
+    }
+}